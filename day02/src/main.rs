@@ -1,8 +1,9 @@
 use clap::{App, Arg};
 use common::{error, Res};
-use std::fs;
-
-type IntCode = i32;
+use intcode::diff::{diff, watch, Change, Snapshot};
+use intcode::loader::{load, Program};
+use intcode::patch::patch;
+use intcode::{Dialect, IntCode};
 
 /*
  * Validate args, parse input, and run program.
@@ -16,6 +17,27 @@ fn main() -> Res<()> {
                 .index(1),
         )
         .arg(Arg::with_name("target").help("Set the target output of the given program."))
+        .arg(
+            Arg::with_name("diff")
+                .long("diff")
+                .value_name("NOUN/VERB")
+                .min_values(1)
+                .max_values(2)
+                .help("Show memory changed by a run, or the difference between two runs."),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .long("watch")
+                .value_name("ADDRS")
+                .help("Comma-separated addresses to report whenever they change."),
+        )
+        .arg(
+            Arg::with_name("dialect")
+                .long("dialect")
+                .value_name("DIALECT")
+                .possible_values(&["day02", "day05", "full"])
+                .help("Instruction set to run with; defaults to the program's, or day02."),
+        )
         .get_matches();
 
     let filename = args.value_of("INPUT").unwrap();
@@ -23,34 +45,57 @@ fn main() -> Res<()> {
         .value_of("target")
         .map(|target_str| target_str.parse::<IntCode>().unwrap());
 
-    // Load and parse list of numbers.
-    let contents = fs::read_to_string(filename)?;
-
-    let intcodes: Vec<IntCode> = {
-        let mut intcodes: Vec<IntCode> = vec![];
-        for (index, line) in contents.trim().split(",").enumerate() {
-            if let Ok(parsed) = line.parse::<IntCode>() {
-                intcodes.push(parsed);
-            } else {
-                println!("Error on integer #{}: could not parse \"{}\"", index, line);
-                std::process::exit(1);
-            }
-        }
-        intcodes
+    let mut program = load(filename)?;
+    let dialect = match args.value_of("dialect") {
+        Some(name) => name.parse::<Dialect>()?,
+        None => program.metadata.dialect.unwrap_or(Dialect::Day02),
     };
-    println!("Loaded {} intcodes.", intcodes.len());
+    program.metadata.dialect = Some(dialect);
+    println!("Loaded {} intcodes ({}).", program.code.len(), dialect);
+
+    if let Some(values) = args.values_of("diff") {
+        let runs = values
+            .map(parse_noun_verb)
+            .collect::<Res<Vec<(IntCode, IntCode)>>>()?;
+        let (noun, verb) = runs[0];
+        let first = patch_noun_verb(&program, noun, verb)?;
+        let changes = if let Some(&(noun, verb)) = runs.get(1) {
+            let first = Snapshot::run(&mut first.machine())?;
+            let second = Snapshot::run(&mut patch_noun_verb(&program, noun, verb)?.machine())?;
+            diff(&first, &second)
+        } else {
+            let before = Snapshot::new(&first.code);
+            diff(&before, &Snapshot::run(&mut first.machine())?)
+        };
+        print_changes(&changes);
+        return Ok(());
+    }
+
+    if let Some(addrs) = args.value_of("watch") {
+        let addrs = addrs
+            .split(',')
+            .map(|addr| addr.trim().parse::<usize>())
+            .collect::<Result<Vec<usize>, _>>()?;
+        let mut machine = patch_noun_verb(&program, 12, 2)?.machine();
+        watch(&mut machine, &addrs, |step, write| {
+            println!(
+                "pc {:>4} ({}): [{}] {} -> {}",
+                step.pc, step.instruction, write.addr, write.old, write.new
+            );
+        })?;
+        println!("result: {}", machine.memory()[0]);
+        return Ok(());
+    }
 
     // Replace values for 1202 state:
     if let Some(target_output) = target_output_opt {
         for noun in 0..99 {
             for verb in 0..99 {
-                let mut intcodes = intcodes.clone();
-                intcodes[1] = noun;
-                intcodes[2] = verb;
+                let mut patched = patch_noun_verb(&program, noun, verb)?;
 
-                run(&mut intcodes)?;
+                run(&mut patched)?;
 
-                let result = intcodes[0];
+                let result = patched.code[0];
                 if result == target_output {
                     println!("match: noun={} verb={}", noun, verb);
                     println!("       100 * noun + verb={}", 100 * noun + verb);
@@ -58,98 +103,137 @@ fn main() -> Res<()> {
             }
         }
     } else {
-        let mut intcodes = intcodes.clone();
         println!("Assuming 1202 output.");
-        intcodes[1] = 12;
-        intcodes[2] = 2;
+        let mut patched = patch_noun_verb(&program, 12, 2)?;
 
-        run(&mut intcodes)?;
-        println!("result: {}", intcodes[0]);
+        run(&mut patched)?;
+        println!("result: {}", patched.code[0]);
     }
 
     Ok(())
 }
 
-/// Run the given IntCode program.
-fn run(mut intcodes: &mut Vec<IntCode>) -> Res<()> {
-    let mut pc = 0;
+/// Copy the program with the given noun and verb patched in.
+fn patch_noun_verb(program: &Program, noun: IntCode, verb: IntCode) -> Res<Program> {
+    Ok(Program {
+        metadata: program.metadata.clone(),
+        code: patch(&program.code, &[(1, noun), (2, verb)])?,
+    })
+}
 
-    while pc < intcodes.len() {
-        let op_code = intcodes[pc];
-        match op_code {
-            1 => {
-                let (src0, src1, dst_addr) = get_operands_and_dst(&mut intcodes, pc)?;
-                intcodes[dst_addr] = src0 + src1;
-            }
-            2 => {
-                let (src0, src1, dst_addr) = get_operands_and_dst(&mut intcodes, pc)?;
-                intcodes[dst_addr] = src0 * src1;
-            }
-            99 => {
-                // Reached the end; exit.
-                break;
-            }
-            _ => {
-                return error(format!("Invalid opcode: {}", op_code));
-            }
-        }
-        pc += 4;
+/// Parse a "noun/verb" pair such as "12/2".
+fn parse_noun_verb(value: &str) -> Res<(IntCode, IntCode)> {
+    let parts: Vec<&str> = value.split('/').collect();
+    if parts.len() != 2 {
+        return error(format!("Expected NOUN/VERB, got \"{}\"", value));
     }
-    Ok(())
+    Ok((parts[0].trim().parse()?, parts[1].trim().parse()?))
 }
 
-/// Get the two operands and destination address for the given program counter.
-fn get_operands_and_dst(intcodes: &mut Vec<IntCode>, pc: usize) -> Res<(IntCode, IntCode, usize)> {
-    let src0_addr = intcodes[pc + 1] as usize;
-    let src1_addr = intcodes[pc + 2] as usize;
-    if src0_addr >= intcodes.len() {
-        return error(format!("Invalid src address: {}", src0_addr));
-    }
-    if src1_addr >= intcodes.len() {
-        return error(format!("Invalid src address: {}", src1_addr));
-    }
-    let src0 = intcodes[intcodes[pc + 1] as usize];
-    let src1 = intcodes[intcodes[pc + 2] as usize];
-    let dst_addr = intcodes[pc + 3] as usize;
-    if dst_addr >= intcodes.len() {
-        return error(format!("Invalid dest address: {}", dst_addr));
+fn print_changes(changes: &[Change]) {
+    let writer = |pc: Option<usize>| match pc {
+        Some(pc) => format!("pc {}", pc),
+        None => "-".to_string(),
+    };
+    println!("{} changed cells:", changes.len());
+    println!(
+        "{:>6} {:>12} {:>12}  last written by",
+        "addr", "before", "after"
+    );
+    for change in changes {
+        println!(
+            "{:>6} {:>12} {:>12}  {} / {}",
+            change.addr,
+            change.before,
+            change.after,
+            writer(change.before_writer),
+            writer(change.after_writer)
+        );
     }
-    Ok((src0, src1, dst_addr))
+}
+
+/// Run the given IntCode program from its entry point, leaving the final memory state in its
+/// code.
+fn run(program: &mut Program) -> Res<()> {
+    let mut machine = program.machine();
+    machine.run()?;
+    program.code = machine.into_memory();
+    Ok(())
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    fn day02(code: Vec<IntCode>) -> Program {
+        let mut program = Program::new(code);
+        program.metadata.dialect = Some(Dialect::Day02);
+        program
+    }
+
     #[test]
     fn given_example_1() {
-        let mut intcodes: Vec<IntCode> = vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
-        run(&mut intcodes).unwrap();
-        assert_eq!(intcodes[0], 3500);
+        let mut program = day02(vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50]);
+        run(&mut program).unwrap();
+        assert_eq!(program.code[0], 3500);
     }
     #[test]
     fn given_example_2() {
-        let mut intcodes: Vec<IntCode> = vec![1, 0, 0, 0, 99];
-        run(&mut intcodes).unwrap();
-        assert_eq!(intcodes[0], 2);
+        let mut program = day02(vec![1, 0, 0, 0, 99]);
+        run(&mut program).unwrap();
+        assert_eq!(program.code[0], 2);
     }
     #[test]
     fn given_example_3() {
-        let mut intcodes: Vec<IntCode> = vec![2, 3, 0, 3, 99];
-        run(&mut intcodes).unwrap();
-        assert_eq!(intcodes[3], 6);
+        let mut program = day02(vec![2, 3, 0, 3, 99]);
+        run(&mut program).unwrap();
+        assert_eq!(program.code[3], 6);
     }
     #[test]
     fn given_example_4() {
-        let mut intcodes: Vec<IntCode> = vec![2, 4, 4, 5, 99, 0];
-        run(&mut intcodes).unwrap();
-        assert_eq!(intcodes[5], 9801);
+        let mut program = day02(vec![2, 4, 4, 5, 99, 0]);
+        run(&mut program).unwrap();
+        assert_eq!(program.code[5], 9801);
     }
     #[test]
     fn given_example_5() {
-        let mut intcodes: Vec<IntCode> = vec![1, 1, 1, 4, 99, 5, 6, 0, 99];
-        run(&mut intcodes).unwrap();
-        assert_eq!(intcodes[0], 30);
-        assert_eq!(intcodes[4], 2);
+        let mut program = day02(vec![1, 1, 1, 4, 99, 5, 6, 0, 99]);
+        run(&mut program).unwrap();
+        assert_eq!(program.code[0], 30);
+        assert_eq!(program.code[4], 2);
+    }
+
+    #[test]
+    fn day02_dialect_is_strict() {
+        let mut program = day02(vec![1002, 4, 3, 4, 33]);
+        assert!(run(&mut program).is_err());
+        program.metadata.dialect = Some(Dialect::Day05);
+        assert!(run(&mut program).is_ok());
+        assert_eq!(program.code[4], 99);
+
+        let mut program = day02(vec![3, 0, 99]);
+        assert!(run(&mut program).is_err());
+    }
+
+    #[test]
+    fn starts_at_entry() -> Res<()> {
+        // From 0 it halts straight away; from 4 it adds the noun and verb into [0].
+        let program = Program::parse("@dialect day02\n@entry 4\n99,0,0,0,1,1,2,0,99")?;
+        let mut patched = patch_noun_verb(&program, 12, 2)?;
+        run(&mut patched)?;
+        assert_eq!(patched.code[0], 14);
+        Ok(())
+    }
+
+    #[test]
+    fn noun_verb() -> Res<()> {
+        assert_eq!(parse_noun_verb("12/2")?, (12, 2));
+        assert!(parse_noun_verb("12").is_err());
+        assert_eq!(
+            patch_noun_verb(&day02(vec![1, 0, 0, 0, 99]), 12, 2)?.code,
+            vec![1, 12, 2, 0, 99]
+        );
+        assert!(patch_noun_verb(&day02(vec![99]), 12, 2).is_err());
+        Ok(())
     }
 }
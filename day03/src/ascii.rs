@@ -0,0 +1,175 @@
+use crate::{corners, Coord, Direction, Position, Span};
+use common::{error, Res};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+/*
+ * Wire diagrams as text, drawn like the puzzle's examples: `o` for the origin, `-` and `|`
+ * along spans, `+` where a wire turns, `X` where different wires meet and `.` elsewhere.
+ * Up is towards the top of the diagram, which is decreasing y. Diagonal spans are drawn with
+ * `\\` and `/`, and cells a wire passes through moving between layers with `*`. Each layer
+ * gets its own diagram, headed with its number, unless the wires all stay on layer 0.
+ */
+
+fn symbol(direction: Direction) -> char {
+    match direction {
+        Direction::Left | Direction::Right => '-',
+        Direction::Up | Direction::Down => '|',
+        Direction::UpLeft | Direction::DownRight => '\\',
+        Direction::UpRight | Direction::DownLeft => '/',
+        Direction::Above | Direction::Below => '*',
+    }
+}
+
+/// The diagram, with a border of one empty cell around each layer. Fails rather than draw
+/// more than `max_cells` characters, not counting newlines and headers.
+pub fn render_ascii(wires: &[Vec<Span>], max_cells: usize) -> Res<String> {
+    // Bounds are found from the corners first, so the size is checked before drawing.
+    let (mut min, mut max): (Position, Position) = ((0, 0, 0), (0, 0, 0));
+    for wire in wires {
+        for (x, y, layer) in corners(wire)? {
+            min = (min.0.min(x), min.1.min(y), min.2.min(layer));
+            max = (max.0.max(x), max.1.max(y), max.2.max(layer));
+        }
+    }
+    // Widened, as the border can take the size past the range of coordinates.
+    let width = i128::from(max.0) - i128::from(min.0) + 3;
+    let height = i128::from(max.1) - i128::from(min.1) + 3;
+    let layers = i128::from(max.2) - i128::from(min.2) + 1;
+    if width.saturating_mul(height).saturating_mul(layers) > max_cells as i128 {
+        return error(format!(
+            "Diagram would be {}x{} on {} layers, more than {} cells",
+            width, height, layers, max_cells
+        ));
+    }
+
+    // What's drawn in each cell, and the wire that drew it.
+    let mut cells: HashMap<Position, (usize, char)> = HashMap::new();
+    for (index, wire) in wires.iter().enumerate() {
+        let spans: Vec<&Span> = wire.iter().filter(|span| span.dist > 0).collect();
+        let mut position: Position = (0, 0, 0);
+        for (i, span) in spans.iter().enumerate() {
+            let line = symbol(span.direction);
+            // Turning onto another axis, not just doubling back along the same one.
+            let turns = spans
+                .get(i + 1)
+                .is_some_and(|next| symbol(next.direction) != line);
+            for step in 1..=span.dist {
+                position = span.direction.step(position);
+                let symbol = if step == span.dist && turns {
+                    '+'
+                } else {
+                    line
+                };
+                let drawn = match cells.get(&position) {
+                    Some(&(owner, _)) if owner != index => (owner, 'X'),
+                    Some(&(owner, 'X')) => (owner, 'X'),
+                    // A wire crossing itself.
+                    Some(&(owner, previous)) if previous != symbol => (owner, '+'),
+                    _ => (index, symbol),
+                };
+                cells.insert(position, drawn);
+            }
+        }
+    }
+
+    let mut out = String::new();
+    for layer in min.2..=max.2 {
+        if (min.2, max.2) != (0, 0) {
+            out.push_str(&format!("layer {}:\n", layer));
+        }
+        for y in i128::from(min.1) - 1..=i128::from(max.1) + 1 {
+            for x in i128::from(min.0) - 1..=i128::from(max.0) + 1 {
+                // Border cells beyond the range of coordinates are empty.
+                let position = match (Coord::try_from(x), Coord::try_from(y)) {
+                    (Ok(x), Ok(y)) => (x, y, layer),
+                    _ => {
+                        out.push('.');
+                        continue;
+                    }
+                };
+                out.push(match cells.get(&position) {
+                    _ if position == (0, 0, 0) => 'o',
+                    Some(&(_, symbol)) => symbol,
+                    None => '.',
+                });
+            }
+            out.push('\n');
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parse_wires, Moves};
+
+    fn diagram(input: &str) -> Res<String> {
+        render_ascii(&parse_wires(input)?, 1000)
+    }
+
+    #[test]
+    fn given_example() -> Res<()> {
+        let expected = "\
+            ...........\n\
+            .+-----+...\n\
+            .|.....|...\n\
+            .|..+--X-+.\n\
+            .|..|..|.|.\n\
+            .|.-X--+.|.\n\
+            .|..|....|.\n\
+            .|.......|.\n\
+            .o-------+.\n\
+            ...........\n";
+        assert_eq!(diagram("R8,U5,L5,D3\nU7,R6,D4,L4")?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn directions() -> Res<()> {
+        // Up goes towards the top, even though it decreases y.
+        assert_eq!(diagram("U2,R1")?, "....\n.+-.\n.|..\n.o..\n....\n");
+        assert_eq!(diagram("D1,L2")?, ".....\n...o.\n.--+.\n.....\n");
+        // Crossing itself.
+        assert_eq!(
+            diagram("R2,U1,L1,D2")?,
+            ".....\n..++.\n.o++.\n..|..\n.....\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn diagonals_and_layers() -> Res<()> {
+        let draw = |input: &str| render_ascii(&Moves::ALL.parse_wires(input)?, 1000);
+        assert_eq!(
+            draw("DR2,UR1")?,
+            "......\n.o....\n..\\./.\n...+..\n......\n"
+        );
+        assert_eq!(draw("UL1,DL1")?, ".....\n..+..\n./.o.\n.....\n");
+        // Doubling back along the same axis isn't a turn.
+        assert_eq!(draw("DR1,UL1")?, "....\n.o..\n..\\.\n....\n");
+        assert_eq!(
+            draw("R1,A2,L1\nA1,R1")?,
+            "layer 0:\n....\n.o+.\n....\n\
+             layer 1:\n....\n.+X.\n....\n\
+             layer 2:\n....\n.-+.\n....\n"
+        );
+        assert!(render_ascii(&Moves::ALL.parse_wires("A10")?, 90).is_err());
+        assert!(render_ascii(&Moves::ALL.parse_wires("A9")?, 90).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn size_cap() -> Res<()> {
+        let wires = parse_wires("R1000000,U1000000")?;
+        assert!(render_ascii(&wires, 1000).is_err());
+        assert_eq!(
+            render_ascii(&parse_wires("R1")?, 12)?,
+            "....\n.o-.\n....\n".to_string()
+        );
+        assert!(render_ascii(&parse_wires("R1")?, 11).is_err());
+        assert!(render_ascii(&parse_wires("R2")?, 14).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,973 @@
+use common::{error, Res};
+use line::{segments, Line, Segment, AXES};
+use parse::{parse_defs, WireDef};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+pub mod ascii;
+pub mod line;
+pub mod loops;
+pub mod pairs;
+pub mod parse;
+pub mod report;
+pub mod route;
+pub mod svg;
+pub mod sweep;
+pub mod wireset;
+
+/// Coordinates and distances. Wires from `parse_wires` are checked to stay within its range.
+/// This is fixed at `i64` rather than generic over the integer type: nothing needs another
+/// width, and arithmetic that can overflow is checked or widened to `i128` instead.
+pub type Coord = i64;
+
+/// A cell: x, y and layer. Wires that don't change layer stay on layer 0.
+pub type Position = (Coord, Coord, Coord);
+
+/// One of the moves a wire can make. Up decreases y, and Above increases the layer. Which moves
+/// a wire may use depends on its `Moves`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+    UpLeft,
+    UpRight,
+    DownLeft,
+    DownRight,
+    Above,
+    Below,
+}
+
+impl Direction {
+    pub const ALL: [Direction; 10] = [
+        Direction::Up,
+        Direction::Down,
+        Direction::Left,
+        Direction::Right,
+        Direction::UpLeft,
+        Direction::UpRight,
+        Direction::DownLeft,
+        Direction::DownRight,
+        Direction::Above,
+        Direction::Below,
+    ];
+
+    /// How far one step moves.
+    pub fn vector(self) -> Position {
+        match self {
+            Direction::Up => (0, -1, 0),
+            Direction::Down => (0, 1, 0),
+            Direction::Left => (-1, 0, 0),
+            Direction::Right => (1, 0, 0),
+            Direction::UpLeft => (-1, -1, 0),
+            Direction::UpRight => (1, -1, 0),
+            Direction::DownLeft => (-1, 1, 0),
+            Direction::DownRight => (1, 1, 0),
+            Direction::Above => (0, 0, 1),
+            Direction::Below => (0, 0, -1),
+        }
+    }
+
+    /// The direction moving by the vector in one step, if there is one.
+    pub fn from_vector(vector: Position) -> Option<Direction> {
+        Direction::ALL
+            .iter()
+            .find(|direction| direction.vector() == vector)
+            .cloned()
+    }
+
+    /// The neighbouring position in this direction.
+    pub fn step(self, (x, y, layer): Position) -> Position {
+        let (dx, dy, dl) = self.vector();
+        (x + dx, y + dy, layer + dl)
+    }
+
+    /// Like `step`, but `None` if the neighbour is beyond the range of coordinates.
+    pub fn checked_step(self, position: Position) -> Option<Position> {
+        self.checked_move(position, 1)
+    }
+
+    /// The position `dist` steps away in this direction, or `None` if it's beyond the range of
+    /// coordinates.
+    pub fn checked_move(self, (x, y, layer): Position, dist: Coord) -> Option<Position> {
+        let (dx, dy, dl) = self.vector();
+        Some((
+            x.checked_add(dx.checked_mul(dist)?)?,
+            y.checked_add(dy.checked_mul(dist)?)?,
+            layer.checked_add(dl.checked_mul(dist)?)?,
+        ))
+    }
+
+    fn code(self) -> &'static str {
+        match self {
+            Direction::Up => "U",
+            Direction::Down => "D",
+            Direction::Left => "L",
+            Direction::Right => "R",
+            Direction::UpLeft => "UL",
+            Direction::UpRight => "UR",
+            Direction::DownLeft => "DL",
+            Direction::DownRight => "DR",
+            Direction::Above => "A",
+            Direction::Below => "B",
+        }
+    }
+}
+
+impl FromStr for Direction {
+    type Err = Box<dyn Error>;
+
+    fn from_str(dir_str: &str) -> Res<Self> {
+        match Direction::ALL.iter().find(|d| d.code() == dir_str) {
+            Some(&direction) => Ok(direction),
+            None => error(format!("Invalid direction: {}", dir_str)),
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+/// Which directions wires may move in. Every move is one step of wire, diagonal or not.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Moves {
+    /// `UL`, `UR`, `DL` and `DR` as well as `U`, `D`, `L` and `R`.
+    pub diagonal: bool,
+    /// `A` to the layer above and `B` to the layer below.
+    pub layers: bool,
+}
+
+impl Moves {
+    pub const FOUR_WAY: Moves = Moves {
+        diagonal: false,
+        layers: false,
+    };
+    pub const EIGHT_WAY: Moves = Moves {
+        diagonal: true,
+        layers: false,
+    };
+    /// Four ways on each layer, plus moves between layers.
+    pub const LAYERED: Moves = Moves {
+        diagonal: false,
+        layers: true,
+    };
+    pub const ALL: Moves = Moves {
+        diagonal: true,
+        layers: true,
+    };
+
+    pub fn allows(self, direction: Direction) -> bool {
+        match direction {
+            Direction::Up | Direction::Down | Direction::Left | Direction::Right => true,
+            Direction::Above | Direction::Below => self.layers,
+            _ => self.diagonal,
+        }
+    }
+
+    pub fn directions(self) -> Vec<Direction> {
+        Direction::ALL
+            .iter()
+            .cloned()
+            .filter(|&direction| self.allows(direction))
+            .collect()
+    }
+
+    /// A span, which must move in one of the allowed directions.
+    pub fn parse_span(self, span_str: &str) -> Res<Span> {
+        let span: Span = span_str.parse()?;
+        if !self.allows(span.direction) {
+            return error(format!("Invalid direction: {}", span.direction));
+        }
+        Ok(span)
+    }
+
+    /// The wires in the input, in the format described in `parse`, with their names and
+    /// lines.
+    pub fn parse_wire_defs(self, contents: &str) -> Res<Vec<WireDef<Span>>> {
+        parse_defs(
+            contents,
+            |span_str| self.parse_span(span_str),
+            |spans| corners(spans).map(|_| ()),
+        )
+    }
+
+    pub fn parse_wires(self, contents: &str) -> Res<Vec<Vec<Span>>> {
+        Ok(self
+            .parse_wire_defs(contents)?
+            .into_iter()
+            .map(|def| def.spans)
+            .collect())
+    }
+}
+
+impl FromStr for Moves {
+    type Err = Box<dyn Error>;
+
+    fn from_str(moves_str: &str) -> Res<Self> {
+        match moves_str {
+            "four" => Ok(Moves::FOUR_WAY),
+            "eight" => Ok(Moves::EIGHT_WAY),
+            "layers" => Ok(Moves::LAYERED),
+            "all" => Ok(Moves::ALL),
+            _ => error(format!("Invalid moves: {}", moves_str)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Span {
+    pub direction: Direction,
+    pub dist: Coord,
+}
+
+/// A direction then a distance, e.g. `R8` or `UL3`. Any direction is accepted here; use
+/// `Moves::parse_span` to only allow some.
+impl FromStr for Span {
+    type Err = Box<dyn Error>;
+
+    fn from_str(span_str: &str) -> Res<Self> {
+        if span_str.is_empty() {
+            return error("Empty span");
+        }
+        let split = span_str
+            .find(|c: char| !c.is_alphabetic())
+            .unwrap_or(span_str.len());
+        let (direction, dist_str) = span_str.split_at(split);
+        Ok(Span {
+            direction: direction.parse()?,
+            dist: parse_dist(span_str, dist_str)?,
+        })
+    }
+}
+
+/// The distance part of a span. Only digits are allowed, so distances can't be negative. Zero
+/// is allowed, and moves nowhere.
+pub fn parse_dist(span_str: &str, dist_str: &str) -> Res<Coord> {
+    // A minus sign is let through so negative distances get a clearer error.
+    let digits = dist_str.strip_prefix('-').unwrap_or(dist_str);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return error(format!("Invalid distance in {}", span_str));
+    }
+    let dist = match dist_str.parse::<Coord>() {
+        Ok(dist) => dist,
+        Err(_) => return error(format!("Distance in {} is too large", span_str)),
+    };
+    if dist < 0 {
+        return error(format!("Distance in {} is negative", span_str));
+    }
+    Ok(dist)
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.direction, self.dist)
+    }
+}
+
+/// The wire in the input format, e.g. `R8,U5`.
+pub fn format_wire(wire: &[Span]) -> String {
+    let spans: Vec<String> = wire.iter().map(Span::to_string).collect();
+    spans.join(",")
+}
+
+/// The spans walking through each position in turn, one step apart, starting from the first.
+/// Panics if two positions in a row aren't one move apart.
+pub fn from_path(path: &[Position]) -> Vec<Span> {
+    let mut spans: Vec<Span> = vec![];
+    for pair in path.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        let direction = Direction::from_vector((b.0 - a.0, b.1 - a.1, b.2 - a.2))
+            .expect("Positions on a path are one move apart");
+        match spans.last_mut() {
+            Some(span) if span.direction == direction => span.dist += 1,
+            _ => spans.push(Span { direction, dist: 1 }),
+        }
+    }
+    spans
+}
+
+/// The four-way wires in the input, in the format described in `parse`, with their names and
+/// lines.
+pub fn parse_wire_defs<S: Into<String>>(contents: S) -> Res<Vec<WireDef<Span>>> {
+    Moves::FOUR_WAY.parse_wire_defs(&contents.into())
+}
+
+pub fn parse_wires<S: Into<String>>(contents: S) -> Res<Vec<Vec<Span>>> {
+    Moves::FOUR_WAY.parse_wires(&contents.into())
+}
+
+pub fn get_positions(wire: &[Span]) -> Vec<Position> {
+    // Create a set of all positions "visited" by the wire.
+    let mut positions = vec![];
+    let mut current_position: Position = (0, 0, 0);
+    for span in wire {
+        for _ in 0..span.dist {
+            let new_position = span.direction.step(current_position);
+            positions.push(new_position);
+            current_position = new_position;
+        }
+    }
+    positions
+}
+
+/// The origin and the end of every span, i.e. the wire's corners. Fails if the wire leaves
+/// the range of `Coord`.
+pub fn corners(wire: &[Span]) -> Res<Vec<Position>> {
+    let mut position: Position = (0, 0, 0);
+    let mut corners = vec![position];
+    for (index, span) in wire.iter().enumerate() {
+        position = match span.direction.checked_move(position, span.dist) {
+            Some(position) => position,
+            None => {
+                return error(format!(
+                    "Span {} ({}) overflows the coordinates",
+                    index, span
+                ))
+            }
+        };
+        corners.push(position);
+    }
+    Ok(corners)
+}
+
+pub fn get_positions_set(wire: &[Span]) -> HashSet<Position> {
+    get_positions(wire).into_iter().collect()
+}
+
+pub fn all_intersections(wires: &[Vec<Span>]) -> HashSet<Position> {
+    let sets: Vec<HashSet<Position>> = wires.iter().map(|wire| get_positions_set(wire)).collect();
+
+    // Now determine all common points in the sets.
+    let mut common_values = match sets.first() {
+        Some(first) => first.clone(),
+        None => return HashSet::new(),
+    };
+    for other in &sets[1..] {
+        common_values = common_values.intersection(other).copied().collect();
+    }
+
+    common_values
+}
+
+/// How to find the points where wires cross.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Algorithm {
+    /// Expand every wire into the cells it visits and intersect the sets. Memory grows with
+    /// wire length.
+    Raster,
+    /// Treat each span as a segment and sweep across them. Time and memory grow with the
+    /// number of spans and crossings instead.
+    Sweep,
+}
+
+impl FromStr for Algorithm {
+    type Err = Box<dyn Error>;
+
+    fn from_str(algorithm_str: &str) -> Res<Self> {
+        match algorithm_str {
+            "raster" => Ok(Self::Raster),
+            "sweep" => Ok(Self::Sweep),
+            _ => error(format!("Invalid algorithm: {}", algorithm_str)),
+        }
+    }
+}
+
+/// Points visited by every wire, found with the given algorithm.
+pub fn intersections(wires: &[Vec<Span>], algorithm: Algorithm) -> HashSet<Position> {
+    match algorithm {
+        Algorithm::Raster => all_intersections(wires),
+        Algorithm::Sweep => sweep::all_intersections(wires),
+    }
+}
+
+/// How to measure distance from the origin.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Metric {
+    /// Sum of the distances along each axis.
+    Manhattan,
+    /// Largest distance along any axis, i.e. moves when diagonals are allowed.
+    Chebyshev,
+    /// Straight line.
+    Euclidean,
+}
+
+/// A distance by one of the metrics. Manhattan and Chebyshev distances are whole steps; only
+/// Euclidean distances need a float.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Distance {
+    Steps(Coord),
+    Straight(f64),
+}
+
+impl fmt::Display for Distance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Distance::Steps(steps) => write!(f, "{}", steps),
+            Distance::Straight(dist) => write!(f, "{}", dist),
+        }
+    }
+}
+
+impl Metric {
+    /// Distance from the origin. Fails if a Manhattan or Chebyshev distance doesn't fit in a
+    /// `Coord`.
+    pub fn distance(self, position: Position) -> Res<Distance> {
+        let key = self.key(position);
+        match self {
+            Metric::Euclidean => Ok(Distance::Straight((key as f64).sqrt())),
+            _ => match Coord::try_from(key) {
+                Ok(steps) => Ok(Distance::Steps(steps)),
+                Err(_) => error(format!("Distance to {:?} overflows", position)),
+            },
+        }
+    }
+
+    /// Exact, and in the same order as distances: squared for Euclidean, so no float is
+    /// needed to compare them. Three squared coordinates always fit.
+    fn key(self, (x, y, layer): Position) -> u128 {
+        let axes = [x, y, layer].map(|a| u128::from(a.unsigned_abs()));
+        match self {
+            Metric::Manhattan => axes.iter().sum(),
+            Metric::Chebyshev => axes.iter().cloned().max().unwrap_or(0),
+            Metric::Euclidean => axes.iter().map(|a| a * a).sum(),
+        }
+    }
+}
+
+impl FromStr for Metric {
+    type Err = Box<dyn Error>;
+
+    fn from_str(metric_str: &str) -> Res<Self> {
+        match metric_str {
+            "manhattan" => Ok(Metric::Manhattan),
+            "chebyshev" => Ok(Metric::Chebyshev),
+            "euclidean" => Ok(Metric::Euclidean),
+            _ => error(format!("Invalid metric: {}", metric_str)),
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Metric::Manhattan => "manhattan",
+            Metric::Chebyshev => "chebyshev",
+            Metric::Euclidean => "euclidean",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The position closest to the origin by the metric, and its distance, or `None` if there are
+/// no positions. Ties go to the smallest position.
+pub fn closest_position(
+    positions: &HashSet<Position>,
+    metric: Metric,
+) -> Res<Option<(Position, Distance)>> {
+    let closest = positions
+        .iter()
+        .map(|&position| (metric.key(position), position))
+        .min();
+    match closest {
+        Some((_, position)) => Ok(Some((position, metric.distance(position)?))),
+        None => Ok(None),
+    }
+}
+
+/// The smallest Manhattan distance of any of the positions, or `None` if there are none.
+pub fn closest_intersection(positions: &HashSet<Position>) -> Res<Option<Coord>> {
+    match closest_position(positions, Metric::Manhattan)? {
+        Some((_, Distance::Steps(dist))) => Ok(Some(dist)),
+        _ => Ok(None),
+    }
+}
+
+/// Distance from the origin, or `None` if it doesn't fit in a `Coord`.
+pub fn manhattan_dist(position: &Position) -> Option<Coord> {
+    let (x, y, layer) = position;
+    x.checked_abs()?
+        .checked_add(y.checked_abs()?)?
+        .checked_add(layer.checked_abs()?)
+}
+
+/// Steps a wire takes to first reach each cell it visits, built in one pass over its spans.
+/// Cells are stored as runs along lines rather than one by one, so long spans cost no more than
+/// short ones.
+#[derive(Clone, Debug, Default)]
+pub struct StepMap {
+    /// For each line, disjoint runs of cells by the `t` they start at: the `t` they end at,
+    /// and the segment that first reached them.
+    runs: HashMap<Line, BTreeMap<i128, (i128, Segment)>>,
+}
+
+impl StepMap {
+    pub fn new(wire: &[Span]) -> StepMap {
+        let mut map = StepMap::default();
+        // Segments come in the order the wire visits them, so each only claims the cells no
+        // earlier segment reached.
+        for segment in segments(wire) {
+            let runs = map.runs.entry(segment.line).or_default();
+            let (lo, hi) = segment.range();
+            let mut gaps = vec![];
+            let mut next = lo;
+            let before = runs.range(..lo).next_back();
+            for (&start, &(end, _)) in before.into_iter().chain(runs.range(lo..=hi)) {
+                if start > next {
+                    gaps.push((next, start - 1));
+                }
+                next = next.max(end + 1);
+            }
+            if next <= hi {
+                gaps.push((next, hi));
+            }
+            for (start, end) in gaps {
+                runs.insert(start, (end, segment));
+            }
+        }
+        map
+    }
+
+    /// Steps to the first visit of the position, or `None` if the wire never reaches it.
+    pub fn get(&self, position: Position) -> Res<Option<usize>> {
+        let mut first: Option<u128> = None;
+        for &axis in &AXES {
+            let (line, t) = Line::through(position, axis);
+            let run = self
+                .runs
+                .get(&line)
+                .and_then(|runs| runs.range(..=t).next_back());
+            if let Some((_, &(end, segment))) = run {
+                if t <= end {
+                    let step = segment.step_at(t);
+                    first = Some(first.map_or(step, |first| first.min(step)));
+                }
+            }
+        }
+        match first.map(usize::try_from) {
+            None => Ok(None),
+            Some(Ok(step)) => Ok(Some(step)),
+            Some(Err(_)) => error(format!("Steps to {:?} overflow", position)),
+        }
+    }
+}
+
+/// An intersection and how far along each wire it is.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Delay {
+    pub position: Position,
+    /// Steps to the first visit, one per wire.
+    pub steps: Vec<usize>,
+    pub total: usize,
+}
+
+/// How far along each wire every one of the positions is, sorted by position. Positions not
+/// on every wire are left out.
+pub fn delays(wires: &[Vec<Span>], positions: &HashSet<Position>) -> Res<Vec<Delay>> {
+    if positions.is_empty() {
+        return Ok(vec![]);
+    }
+    let maps: Vec<StepMap> = wires.iter().map(|wire| StepMap::new(wire)).collect();
+    let mut delays = vec![];
+    for &position in positions {
+        let steps = maps
+            .iter()
+            .map(|map| map.get(position))
+            .collect::<Res<Option<Vec<usize>>>>()?;
+        let steps = match steps {
+            Some(steps) => steps,
+            None => continue,
+        };
+        let total = match steps
+            .iter()
+            .try_fold(0usize, |total, &s| total.checked_add(s))
+        {
+            Some(total) => total,
+            None => return error(format!("Steps to {:?} overflow", position)),
+        };
+        delays.push(Delay {
+            position,
+            steps,
+            total,
+        });
+    }
+    delays.sort_by_key(|delay| delay.position);
+    Ok(delays)
+}
+
+/// The intersection with the fewest combined steps along the wires. Ties go to the smallest
+/// position. Positions not on every wire are ignored.
+pub fn closest_delay(wires: &[Vec<Span>], positions: &HashSet<Position>) -> Res<Option<Delay>> {
+    Ok(delays(wires, positions)?
+        .into_iter()
+        .min_by_key(|delay| (delay.total, delay.position)))
+}
+
+/// The fewest combined steps along the wires to any of the positions, or `None` if none are
+/// on every wire.
+pub fn closest_length(wires: &[Vec<Span>], positions: &HashSet<Position>) -> Res<Option<usize>> {
+    Ok(closest_delay(wires, positions)?.map(|delay| delay.total))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parsing() -> Res<()> {
+        let wires = parse_wires("U10,L5,R3\nD5")?;
+        assert_eq!(
+            wires[0],
+            vec![
+                Span {
+                    direction: Direction::Up,
+                    dist: 10
+                },
+                Span {
+                    direction: Direction::Left,
+                    dist: 5
+                },
+                Span {
+                    direction: Direction::Right,
+                    dist: 3
+                }
+            ]
+        );
+        assert_eq!(
+            wires[1],
+            vec![Span {
+                direction: Direction::Down,
+                dist: 5
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parsing_moves() -> Res<()> {
+        assert_eq!(
+            Moves::ALL.parse_span("UR12")?,
+            Span {
+                direction: Direction::UpRight,
+                dist: 12,
+            }
+        );
+        assert_eq!(Moves::LAYERED.parse_span("B2")?.direction, Direction::Below);
+        assert!(Moves::FOUR_WAY.parse_span("UR12").is_err());
+        assert!(Moves::EIGHT_WAY.parse_span("A1").is_err());
+        assert!(Moves::ALL.parse_span("X1").is_err());
+        assert!(Moves::ALL.parse_span("DL-1").is_err());
+        assert_eq!(
+            Moves::FOUR_WAY.parse_wires("R8,U5\nD1")?,
+            parse_wires("R8,U5\nD1")?
+        );
+        assert!(Moves::ALL.parse_wires("UR9223372036854775807,R1").is_err());
+        assert!(Moves::ALL.parse_wires("A9223372036854775807,A1").is_err());
+        assert_eq!(
+            Moves::ALL.parse_wires("# Diagonals.\r\nX: UR2, A1\r\n")?,
+            Moves::ALL.parse_wires("UR2,A1")?
+        );
+        for &direction in &Direction::ALL {
+            assert_eq!(direction.to_string().parse::<Direction>()?, direction);
+            assert_eq!(Direction::from_vector(direction.vector()), Some(direction));
+        }
+        assert_eq!("layers".parse::<Moves>()?, Moves::LAYERED);
+        Ok(())
+    }
+
+    #[test]
+    fn positions_up() -> Res<()> {
+        let wire = vec![Span {
+            direction: Direction::Up,
+            dist: 3,
+        }];
+        let positions = get_positions_set(&wire);
+        let expected: HashSet<Position> = vec![(0, -1, 0), (0, -2, 0), (0, -3, 0)]
+            .into_iter()
+            .collect();
+
+        assert_eq!(positions, expected);
+        Ok(())
+    }
+    #[test]
+    fn positions_down() -> Res<()> {
+        let wire = vec![Span {
+            direction: Direction::Down,
+            dist: 3,
+        }];
+        let positions = get_positions(&wire);
+        let expected = vec![(0, 1, 0), (0, 2, 0), (0, 3, 0)];
+
+        assert_eq!(positions, expected);
+        Ok(())
+    }
+    #[test]
+    fn positions_left() -> Res<()> {
+        let wire = vec![Span {
+            direction: Direction::Left,
+            dist: 3,
+        }];
+        let positions = get_positions(&wire);
+        let expected = vec![(-1, 0, 0), (-2, 0, 0), (-3, 0, 0)];
+
+        assert_eq!(positions, expected);
+        Ok(())
+    }
+    #[test]
+    fn positions_right() -> Res<()> {
+        let wire = vec![Span {
+            direction: Direction::Right,
+            dist: 3,
+        }];
+        let positions = get_positions(&wire);
+        let expected = vec![(1, 0, 0), (2, 0, 0), (3, 0, 0)];
+
+        assert_eq!(positions, expected);
+        Ok(())
+    }
+    #[test]
+    fn positions_left_right() -> Res<()> {
+        let wire = vec![
+            Span {
+                direction: Direction::Right,
+                dist: 3,
+            },
+            Span {
+                direction: Direction::Up,
+                dist: 2,
+            },
+        ];
+        let positions = get_positions(&wire);
+        let expected = vec![(1, 0, 0), (2, 0, 0), (3, 0, 0), (3, -1, 0), (3, -2, 0)];
+
+        assert_eq!(positions, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn intersections_1() -> Res<()> {
+        let wires = parse_wires("R8,U5,L5,D3\nU7,R6,D4,L4")?;
+        let intersections = all_intersections(&wires);
+        let expected: HashSet<Position> = vec![(3, -3, 0), (6, -5, 0)].into_iter().collect();
+        assert_eq!(intersections, expected);
+        Ok(())
+    }
+    #[test]
+    fn closest_intersection_1() -> Res<()> {
+        let pos: HashSet<Position> = vec![(3, -3, 0), (6, -5, 0)].into_iter().collect();
+        let closest_dist = closest_intersection(&pos)?;
+        assert_eq!(closest_dist, Some(6));
+        assert_eq!(closest_intersection(&HashSet::new())?, None);
+        Ok(())
+    }
+    #[test]
+    fn given_example_1() -> Res<()> {
+        let wires = parse_wires("R8,U5,L5,D3\nU7,R6,D4,L4")?;
+        let intersections = all_intersections(&wires);
+        let expected: HashSet<Position> = vec![(3, -3, 0), (6, -5, 0)].into_iter().collect();
+        assert_eq!(intersections, expected);
+        let closest_by_wire_length = closest_length(&wires, &intersections)?;
+        assert_eq!(closest_by_wire_length, Some(30));
+        Ok(())
+    }
+    #[test]
+    fn given_example_2() -> Res<()> {
+        let wires =
+            parse_wires("R75,D30,R83,U83,L12,D49,R71,U7,L72\nU62,R66,U55,R34,D71,R55,D58,R83")?;
+        let intersections = all_intersections(&wires);
+        let closest_by_wire_length = closest_length(&wires, &intersections)?;
+        assert_eq!(closest_by_wire_length, Some(610));
+        Ok(())
+    }
+    #[test]
+    fn given_example_3() -> Res<()> {
+        let wires = parse_wires(
+            "R98,U47,R26,D63,R33,U87,L62,D20,R33,U53,R51\nU98,R91,D20,R16,D67,R40,U7,R15,U6,R7",
+        )?;
+        let intersections = all_intersections(&wires);
+        let closest_by_wire_length = closest_length(&wires, &intersections)?;
+        assert_eq!(closest_by_wire_length, Some(410));
+        Ok(())
+    }
+
+    #[test]
+    fn diagonals() -> Res<()> {
+        // Crossing at a cell, and crossing between cells.
+        let wires = Moves::EIGHT_WAY.parse_wires("DR4\nR4,DL4")?;
+        assert_eq!(
+            all_intersections(&wires),
+            vec![(2, 2, 0)].into_iter().collect()
+        );
+        assert_eq!(closest_length(&wires, &all_intersections(&wires))?, Some(8));
+        let wires = Moves::EIGHT_WAY.parse_wires("DR2\nR1,DL1")?;
+        assert!(all_intersections(&wires).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn layers() -> Res<()> {
+        // Both wires run along y = 0 on different layers, so they only meet once the first
+        // climbs to the second's layer, until the second drops back down.
+        let wires = Moves::LAYERED.parse_wires("R3,A1,D3\nA1,R3,D2,B1,D1")?;
+        let positions = all_intersections(&wires);
+        assert_eq!(
+            positions,
+            vec![(3, 0, 1), (3, 1, 1), (3, 2, 1)].into_iter().collect()
+        );
+        let delay = closest_delay(&wires, &positions)?.unwrap();
+        assert_eq!((delay.position, delay.total), ((3, 0, 1), 8));
+        assert_eq!(closest_intersection(&positions)?, Some(4));
+        Ok(())
+    }
+
+    #[test]
+    fn metrics() -> Res<()> {
+        let position = (3, -4, 2);
+        assert_eq!(Metric::Manhattan.distance(position)?, Distance::Steps(9));
+        assert_eq!(Metric::Chebyshev.distance(position)?, Distance::Steps(4));
+        assert_eq!(
+            Metric::Euclidean.distance((3, -4, 0))?,
+            Distance::Straight(5.0)
+        );
+        let positions = vec![(4, 0, 0), (3, 3, 0)].into_iter().collect();
+        let closest = |metric| closest_position(&positions, metric);
+        assert_eq!(
+            closest(Metric::Manhattan)?,
+            Some(((4, 0, 0), Distance::Steps(4)))
+        );
+        assert_eq!(
+            closest(Metric::Chebyshev)?,
+            Some(((3, 3, 0), Distance::Steps(3)))
+        );
+        assert_eq!(
+            closest(Metric::Euclidean)?,
+            Some(((4, 0, 0), Distance::Straight(4.0)))
+        );
+        assert_eq!(closest_position(&HashSet::new(), Metric::Euclidean)?, None);
+        assert_eq!("chebyshev".parse::<Metric>()?, Metric::Chebyshev);
+        assert_eq!(Metric::Euclidean.to_string(), "euclidean");
+
+        // Whole-step distances are checked, and only Euclidean ones are floats.
+        let max = Coord::MAX;
+        assert_eq!(
+            Metric::Chebyshev.distance((max, Coord::MIN + 1, 0))?,
+            Distance::Steps(max)
+        );
+        assert!(Metric::Chebyshev.distance((Coord::MIN, 0, 0)).is_err());
+        assert!(Metric::Manhattan.distance((max, 0, 1)).is_err());
+        assert!(Metric::Euclidean
+            .distance((Coord::MIN, Coord::MIN, Coord::MIN))
+            .is_ok());
+        Ok(())
+    }
+
+    /// Steps to each cell's first visit, found by visiting every cell.
+    fn visit_every_cell(wire: &[Span]) -> HashMap<Position, usize> {
+        let mut steps = HashMap::new();
+        for (step, position) in get_positions(wire).into_iter().enumerate() {
+            steps.entry(position).or_insert(step + 1);
+        }
+        steps
+    }
+
+    #[test]
+    fn delays() -> Res<()> {
+        let wires = parse_wires("R8,U5,L5,D3\nU7,R6,D4,L4")?;
+        let map = StepMap::new(&wires[0]);
+        assert_eq!(map.get((8, 0, 0))?, Some(8));
+        assert_eq!(map.get((0, 0, 0))?, None);
+        let delay = closest_delay(&wires, &all_intersections(&wires))?.unwrap();
+        assert_eq!(
+            delay,
+            Delay {
+                position: (6, -5, 0),
+                steps: vec![15, 15],
+                total: 30,
+            }
+        );
+        // A loop back over a cell keeps the first visit.
+        assert_eq!(
+            StepMap::new(&parse_wires("R2,L1")?[0]).get((1, 0, 0))?,
+            Some(1)
+        );
+        assert_eq!(
+            StepMap::new(&parse_wires("R0,U1")?[0]).get((0, -1, 0))?,
+            Some(1)
+        );
+        assert_eq!(closest_delay(&wires, &HashSet::new())?, None);
+
+        // Against visiting every cell, on wires that double back over themselves.
+        for wire in parse_wires("R8,U5,L5,D3\nU7,R6,D4,L4\nR5,L3,U2,D4,U2,R6,L9")? {
+            let expected = visit_every_cell(&wire);
+            let map = StepMap::new(&wire);
+            for y in -9..3 {
+                for x in -9..11 {
+                    assert_eq!(map.get((x, y, 0))?, expected.get(&(x, y, 0)).copied());
+                }
+            }
+        }
+        for wire in Moves::ALL.parse_wires("DR3,UL5,A2,R2,DL2,B3,UR4\nUR2,DL4,A1,DR3,B1,UL3")? {
+            let expected = visit_every_cell(&wire);
+            let map = StepMap::new(&wire);
+            for layer in -3..4 {
+                for y in -6..6 {
+                    for x in -6..6 {
+                        let position = (x, y, layer);
+                        assert_eq!(map.get(position)?, expected.get(&position).copied());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn long_delays() -> Res<()> {
+        // Far too long to visit every cell.
+        let wires = parse_wires("R4000000000,U1\nU1,R4000000000")?;
+        let crossings = sweep::all_intersections(&wires);
+        let delay = closest_delay(&wires, &crossings)?.unwrap();
+        assert_eq!(delay.position, (4_000_000_000, -1, 0));
+        assert_eq!(delay.steps, vec![4_000_000_001, 4_000_000_001]);
+        assert_eq!(closest_length(&wires, &crossings)?, Some(8_000_000_002));
+
+        // Only first reached after more steps than fit.
+        let max = Coord::MAX;
+        let wires = parse_wires(format!(
+            "R{},L{},R{}\nU1,R{},D1",
+            max - 1,
+            max - 1,
+            max,
+            max
+        ))?;
+        let crossings = sweep::all_intersections(&wires);
+        assert_eq!(crossings, vec![(max, 0, 0)].into_iter().collect());
+        assert!(closest_length(&wires, &crossings).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn overflow() -> Res<()> {
+        let max = Coord::MAX;
+        assert!(parse_wires(format!("R{}", max)).is_ok());
+        assert!(parse_wires(format!("R{},R1", max)).is_err());
+        assert!(parse_wires(format!("U{},U2", max)).is_err());
+        assert_eq!(manhattan_dist(&(max, 0, 0)), Some(max));
+        assert_eq!(manhattan_dist(&(max, -1, 0)), None);
+        assert_eq!(manhattan_dist(&(Coord::MIN, 0, 0)), None);
+        let far: HashSet<Position> = vec![(max, max, 0)].into_iter().collect();
+        assert!(closest_intersection(&far).is_err());
+        // No intersection is an answer of its own, not a huge distance.
+        let wires = parse_wires("R5\nL5")?;
+        assert_eq!(closest_intersection(&all_intersections(&wires))?, None);
+        assert_eq!(closest_length(&wires, &all_intersections(&wires))?, None);
+        assert!(all_intersections(&[]).is_empty());
+        Ok(())
+    }
+}
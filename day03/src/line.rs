@@ -0,0 +1,167 @@
+use crate::{Coord, Position, Span};
+use std::convert::TryFrom;
+
+/*
+ * Wires as straight runs of cells.
+ *
+ * Every span runs along a line of cells `origin + t * axis`. The axis is the span's step or its
+ * reverse, whichever has its first nonzero component positive, so both ways along a line share
+ * it. That component is 1, so `t` is just the cell's coordinate along it, and the origin is the
+ * cell where `t` is zero. Origins can lie beyond the range of `Coord`, so they and `t` are
+ * widened.
+ */
+
+/// The axis of every line a wire can run along: rows, columns, both diagonals and between
+/// layers.
+pub const AXES: [Position; 5] = [(1, 0, 0), (0, 1, 0), (1, 1, 0), (1, -1, 0), (0, 0, 1)];
+
+type Wide = [i128; 3];
+
+fn widen((x, y, layer): Position) -> Wide {
+    [x.into(), y.into(), layer.into()]
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Line {
+    pub axis: Position,
+    pub origin: Wide,
+}
+
+impl Line {
+    /// The line along the axis through the position, and how far along it the position is.
+    pub fn through(position: Position, axis: Position) -> (Line, i128) {
+        through(widen(position), axis)
+    }
+
+    /// The cell `t` along the line, or `None` if it's beyond the range of `Coord`.
+    pub fn at(&self, t: i128) -> Option<Position> {
+        let axis = widen(self.axis);
+        let coord = |i: usize| Coord::try_from(self.origin[i] + t * axis[i]).ok();
+        Some((coord(0)?, coord(1)?, coord(2)?))
+    }
+}
+
+fn through(position: Wide, axis: Position) -> (Line, i128) {
+    let axis_wide = widen(axis);
+    let t = match axis_wide.iter().position(|&a| a != 0) {
+        Some(i) => position[i],
+        None => 0,
+    };
+    let mut origin = position;
+    for (o, a) in origin.iter_mut().zip(&axis_wide) {
+        *o -= t * a;
+    }
+    (Line { axis, origin }, t)
+}
+
+/// The cells of one span.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Segment {
+    pub line: Line,
+    /// How far along the line the first and last cells are, in the order the wire visits them.
+    pub first: i128,
+    pub last: i128,
+    /// Steps from the origin to the first cell.
+    pub step: u128,
+}
+
+impl Segment {
+    /// The lowest and highest `t` of the cells.
+    pub fn range(&self) -> (i128, i128) {
+        (self.first.min(self.last), self.first.max(self.last))
+    }
+
+    /// Steps from the origin to the cell `t` along the line, which must be on the segment.
+    pub fn step_at(&self, t: i128) -> u128 {
+        self.step + (t - self.first).unsigned_abs()
+    }
+}
+
+/// The wire's spans as segments, in order, leaving out spans that go nowhere.
+pub fn segments(wire: &[Span]) -> Vec<Segment> {
+    let mut segments = vec![];
+    let mut position: Wide = [0, 0, 0];
+    let mut step: u128 = 0;
+    for span in wire {
+        let vector = widen(span.direction.vector());
+        let dist = i128::from(span.dist);
+        if dist > 0 {
+            let mut first = position;
+            for (f, v) in first.iter_mut().zip(&vector) {
+                *f += v;
+            }
+            // The axis is the vector or its reverse, and `t` runs the same way or backwards.
+            let forwards = vector.iter().find(|&&v| v != 0).is_some_and(|&v| v > 0);
+            let axis = if forwards {
+                span.direction.vector()
+            } else {
+                let (x, y, layer) = span.direction.vector();
+                (-x, -y, -layer)
+            };
+            let (line, t) = through(first, axis);
+            let last = if forwards {
+                t + dist - 1
+            } else {
+                t - (dist - 1)
+            };
+            segments.push(Segment {
+                line,
+                first: t,
+                last,
+                step: step + 1,
+            });
+        }
+        for (p, v) in position.iter_mut().zip(&vector) {
+            *p += v * dist;
+        }
+        step += span.dist as u128;
+    }
+    segments
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{get_positions, Moves};
+    use common::Res;
+
+    #[test]
+    fn segments_cover_the_wire() -> Res<()> {
+        let input = "R8,U5,L5,D3\nU7,R0,R6,D4,L4\nL2,R4\nUL3,DR5,A2,UR1,DL4,B1";
+        for wire in Moves::ALL.parse_wires(input)? {
+            let mut cells = vec![];
+            for segment in segments(&wire) {
+                let (lo, hi) = segment.range();
+                assert_eq!(hi - lo, (segment.last - segment.first).abs());
+                let step = |t: i128| segment.step_at(t) as usize;
+                let mut run: Vec<_> = (lo..=hi)
+                    .map(|t| (step(t), segment.line.at(t).unwrap()))
+                    .collect();
+                run.sort_unstable();
+                cells.extend(run.into_iter().map(|(_, position)| position));
+            }
+            assert_eq!(cells, get_positions(&wire));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn lines() {
+        let (line, t) = Line::through((3, -4, 1), (0, 1, 0));
+        assert_eq!((line.origin, t), ([3, 0, 1], -4));
+        assert_eq!(Line::through((3, 7, 1), (0, 1, 0)).0, line);
+        assert_eq!(line.at(-4), Some((3, -4, 1)));
+        assert_eq!(line.at(i128::from(Coord::MAX) + 1), None);
+
+        // Diagonal origins can be beyond the range of coordinates.
+        let (line, t) = Line::through((Coord::MAX, Coord::MIN, 0), (1, -1, 0));
+        assert_eq!(t, i128::from(Coord::MAX));
+        assert_eq!(line.origin, [0, -1, 0]);
+        let (line, _) = Line::through((Coord::MIN, Coord::MAX, 0), (1, 1, 0));
+        assert_eq!(
+            line.origin[1],
+            i128::from(Coord::MAX) - i128::from(Coord::MIN)
+        );
+        assert_eq!(line.at(0), None);
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{from_path, get_positions, Position, Span};
+use std::collections::{BTreeSet, HashMap};
+
+/*
+ * Wires crossing themselves.
+ *
+ * Steps count from the origin, which is step 0, so a wire coming back to the origin closes a
+ * loop too.
+ */
+
+/// A stretch of wire that leaves a cell and comes back to it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Loop {
+    pub position: Position,
+    /// Step at which the wire first reached the cell.
+    pub start: usize,
+    /// Step at which it came back.
+    pub end: usize,
+}
+
+impl Loop {
+    /// Steps taken around the loop.
+    pub fn length(&self) -> usize {
+        self.end - self.start
+    }
+}
+
+fn path(wire: &[Span]) -> Vec<Position> {
+    let mut path = vec![(0, 0, 0)];
+    path.extend(get_positions(wire));
+    path
+}
+
+/// Cells the wire visits more than once, sorted by position, x first.
+pub fn self_crossings(wire: &[Span]) -> Vec<Position> {
+    let mut seen = BTreeSet::new();
+    let mut crossings = BTreeSet::new();
+    for position in path(wire) {
+        if !seen.insert(position) {
+            crossings.insert(position);
+        }
+    }
+    crossings.into_iter().collect()
+}
+
+/// Walk the wire, cutting out each loop as it closes. Returns the loops, in the order they
+/// close, and the cells left on the path. Loops inside a loop that's later cut out are still
+/// reported.
+fn untangle(wire: &[Span]) -> (Vec<Loop>, Vec<Position>) {
+    let mut loops = vec![];
+    // The remaining path, with the step each cell was reached at.
+    let mut kept: Vec<(Position, usize)> = vec![];
+    let mut index: HashMap<Position, usize> = HashMap::new();
+    for (step, position) in path(wire).into_iter().enumerate() {
+        if let Some(&i) = index.get(&position) {
+            let (_, start) = kept[i];
+            loops.push(Loop {
+                position,
+                start,
+                end: step,
+            });
+            for (removed, _) in kept.drain(i + 1..) {
+                index.remove(&removed);
+            }
+        } else {
+            index.insert(position, kept.len());
+            kept.push((position, step));
+        }
+    }
+    (
+        loops,
+        kept.into_iter().map(|(position, _)| position).collect(),
+    )
+}
+
+pub fn loops(wire: &[Span]) -> Vec<Loop> {
+    untangle(wire).0
+}
+
+/// The wire with every loop removed, so it visits no cell twice but ends in the same place.
+pub fn simplify(wire: &[Span]) -> Vec<Span> {
+    let (_, path) = untangle(wire);
+    from_path(&path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{format_wire, parse_wires, Moves};
+    use common::Res;
+
+    fn wire(input: &str) -> Res<Vec<Span>> {
+        Ok(parse_wires(input)?.remove(0))
+    }
+
+    #[test]
+    fn crossings_and_loops() -> Res<()> {
+        // Around a 2x2 square and back through its first corner.
+        let w = wire("R4,U2,L2,D4")?;
+        assert_eq!(self_crossings(&w), vec![(2, 0, 0)]);
+        assert_eq!(
+            loops(&w),
+            vec![Loop {
+                position: (2, 0, 0),
+                start: 2,
+                end: 10,
+            }]
+        );
+        assert_eq!(loops(&w)[0].length(), 8);
+
+        // Straight back to the origin.
+        let w = wire("R2,L2")?;
+        assert_eq!(self_crossings(&w), vec![(0, 0, 0), (1, 0, 0)]);
+        assert_eq!(loops(&w).len(), 2);
+        assert_eq!(loops(&w)[1].length(), 4);
+
+        assert!(loops(&wire("R8,U5,L5,D3")?).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn simplified() -> Res<()> {
+        assert_eq!(format_wire(&simplify(&wire("R4,U2,L2,D4")?)), "R2,D2");
+        assert_eq!(format_wire(&simplify(&wire("R2,L2,U1")?)), "U1");
+        assert_eq!(format_wire(&simplify(&wire("R2,L1,R3")?)), "R4");
+        let w = wire("R8,U5,L5,D3")?;
+        assert_eq!(simplify(&w), w);
+        Ok(())
+    }
+
+    #[test]
+    fn other_moves() -> Res<()> {
+        let w = Moves::ALL.parse_wires("DR3,U2,DL2")?.remove(0);
+        assert_eq!(self_crossings(&w), vec![(2, 2, 0)]);
+        assert_eq!(format_wire(&simplify(&w)), "DR2,DL1");
+        // Up a layer and back down to the origin.
+        let w = Moves::LAYERED.parse_wires("A1,R1,B1,L1,D1")?.remove(0);
+        assert_eq!(
+            loops(&w),
+            vec![Loop {
+                position: (0, 0, 0),
+                start: 0,
+                end: 4,
+            }]
+        );
+        assert_eq!(format_wire(&simplify(&w)), "D1");
+        Ok(())
+    }
+}
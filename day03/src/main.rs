@@ -1,54 +1,18 @@
 use clap::{App, Arg};
 use common::{error, Res};
-use std::collections::HashSet;
+use day03::ascii::render_ascii;
+use day03::loops::{loops, simplify};
+use day03::pairs::{pairwise, render_matrix, shared_by};
+use day03::report::report;
+use day03::route::{route, Avoid, DEFAULT_MAX_EXPANSIONS};
+use day03::svg::render_svg;
+use day03::wireset::WireSet;
+use day03::{
+    closest_length, closest_position, format_wire, intersections, Algorithm, Metric, Moves,
+    Position, Span,
+};
 use std::fs;
 
-type Position = (i32, i32);
-
-#[derive(Debug, Eq, PartialEq)]
-enum Direction {
-    Up,
-    Down,
-    Left,
-    Right,
-}
-
-impl Direction {
-    fn from_str<S: Into<String>>(dir_str: S) -> Res<Self> {
-        let dir_str = dir_str.into();
-        match dir_str.as_ref() {
-            "U" => Ok(Self::Up),
-            "D" => Ok(Self::Down),
-            "R" => Ok(Self::Right),
-            "L" => Ok(Self::Left),
-            _ => error(format!("Invalid direction: {}", dir_str)),
-        }
-    }
-}
-
-#[derive(Debug, Eq, PartialEq)]
-struct Span {
-    direction: Direction,
-    dist: i32,
-}
-
-impl Span {
-    fn from_str<S: Into<String>>(span_str: S) -> Res<Self> {
-        let span_str: String = span_str.into();
-        let mut iter = span_str.chars();
-        if span_str.len() == 0 {
-            return error(format!("Empty span string: {}", span_str));
-        }
-        let first = iter.next().unwrap().to_string();
-        let rest: String = iter.collect();
-
-        Ok(Span {
-            direction: Direction::from_str(first)?,
-            dist: rest.parse::<i32>()?,
-        })
-    }
-}
-
 fn main() -> Res<()> {
     let args = App::new("Advent of Code, Day 3")
         .arg(
@@ -57,268 +21,225 @@ fn main() -> Res<()> {
                 .required(true)
                 .index(1),
         )
+        .arg(
+            Arg::with_name("algorithm")
+                .long("algorithm")
+                .takes_value(true)
+                .possible_values(&["raster", "sweep"])
+                .default_value("sweep")
+                .help("How to find intersections: visit every cell, or sweep across spans."),
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .conflicts_with_all(&["matrix", "shared", "loops", "ascii", "route", "at"])
+                .help("Prints only a JSON report of the wires, intersections and answers."),
+        )
+        .arg(
+            Arg::with_name("matrix")
+                .long("matrix")
+                .help("Prints how many times each pair of wires crosses."),
+        )
+        .arg(
+            Arg::with_name("shared")
+                .long("shared")
+                .takes_value(true)
+                .value_name("K")
+                .help("Lists the cells on at least K wires, and which wires."),
+        )
+        .arg(
+            Arg::with_name("loops")
+                .long("loops")
+                .help("Reports where each wire crosses itself, and the wire without its loops."),
+        )
+        .arg(
+            Arg::with_name("svg")
+                .long("svg")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Draws the wires and their intersections to an SVG file."),
+        )
+        .arg(
+            Arg::with_name("ascii")
+                .long("ascii")
+                .help("Draws small layouts as text."),
+        )
+        .arg(
+            Arg::with_name("moves")
+                .long("moves")
+                .takes_value(true)
+                .possible_values(&["four", "eight", "layers", "all"])
+                .default_value("four")
+                .help("Which moves wires may make: also diagonals, or between layers."),
+        )
+        .arg(
+            Arg::with_name("metric")
+                .long("metric")
+                .takes_value(true)
+                .possible_values(&["manhattan", "chebyshev", "euclidean"])
+                .default_value("manhattan")
+                .help("How to measure the closest intersection."),
+        )
+        .arg(
+            Arg::with_name("route")
+                .long("route")
+                .takes_value(true)
+                .value_name("X,Y[,LAYER]")
+                .help("Finds the shortest new wire from the origin to X,Y avoiding the others."),
+        )
+        .arg(
+            Arg::with_name("cross")
+                .long("cross")
+                .requires("route")
+                .help("Lets the new route cross other wires, as few times as possible."),
+        )
+        .arg(
+            Arg::with_name("max-expansions")
+                .long("max-expansions")
+                .takes_value(true)
+                .value_name("N")
+                .requires("route")
+                .help("Gives up routing after expanding N cells. Defaults to 10 million."),
+        )
+        .arg(
+            Arg::with_name("at")
+                .long("at")
+                .takes_value(true)
+                .value_name("X,Y[,LAYER]")
+                .help("Lists the wires through X,Y and their steps there, or the nearest wire."),
+        )
         .get_matches();
 
     let filename = args.value_of("INPUT").unwrap();
+    let algorithm = args.value_of("algorithm").unwrap().parse::<Algorithm>()?;
 
-    // Load and parse list of numbers.
+    // Load the wire definitions.
     let contents = fs::read_to_string(filename)?;
 
-    // Parse into spans.
-    let wires: Vec<Vec<Span>> = parse_wires(contents)?;
-    println!("Loaded {} wires.", wires.len());
-
-    let intersections = all_intersections(&wires);
-    println!("Intersections: {:?}", intersections);
+    let moves = args.value_of("moves").unwrap().parse::<Moves>()?;
+    let metric = args.value_of("metric").unwrap().parse::<Metric>()?;
 
-    let closest_dist = closest_intersection(&intersections);
-    println!("closest intersection manhattan dist: {}", closest_dist);
-
-    let closest_by_wire_length = closest_length(&wires, &intersections);
-    println!("closest by wire length: {}", closest_by_wire_length);
-
-    Ok(())
-}
-
-fn parse_wires<S: Into<String>>(contents: S) -> Res<Vec<Vec<Span>>> {
-    let contents = contents.into();
-    let wire_defs: Vec<&str> = contents.split("\n").filter(|&s| s.len() > 0).collect();
-    let mut wires: Vec<Vec<Span>> = vec![];
-    for wire_def in wire_defs {
-        let mut spans: Vec<Span> = vec![];
-        for span_str in wire_def.split(",").filter(|&s| s.len() > 0) {
-            spans.push(Span::from_str(span_str)?);
+    // Parse into spans.
+    let defs = moves.parse_wire_defs(&contents)?;
+    let wires: Vec<Vec<Span>> = defs.iter().map(|def| def.spans.clone()).collect();
+
+    if args.is_present("json") {
+        // Nothing else is printed, so the output can go straight to other tools.
+        println!("{}", report(&defs, algorithm, metric)?);
+    } else {
+        print_answers(&wires, algorithm, metric)?;
+    }
+
+    if let Some(position) = args.value_of("at") {
+        let position = parse_position(position)?;
+        let set = WireSet::new(wires.clone());
+        for visit in set.visits(position) {
+            println!(
+                "wire #{} reaches {:?} at step {}",
+                visit.wire, position, visit.step
+            );
+        }
+        if set.visits(position).is_empty() {
+            if let Some((wire, nearest)) = set.nearest(position) {
+                println!(
+                    "no wire at {:?}; nearest is wire #{} at {:?}",
+                    position, wire, nearest
+                );
+            }
         }
-        wires.push(spans);
     }
-    Ok(wires)
-}
 
-fn get_positions(wire: &Vec<Span>) -> Vec<Position> {
-    // Create a set of all positions "visited" by the wire.
-    let mut positions = vec![];
-    let mut current_position: Position = (0, 0);
-    for span in wire {
-        for _ in 0..span.dist {
-            let new_position = match &span.direction {
-                Direction::Up => (current_position.0, current_position.1 - 1),
-                Direction::Down => (current_position.0, current_position.1 + 1),
-                Direction::Right => (current_position.0 + 1, current_position.1),
-                Direction::Left => (current_position.0 - 1, current_position.1),
-            };
-            positions.push(new_position);
-            current_position = new_position;
+    if let Some(target) = args.value_of("route") {
+        let target = parse_position(target)?;
+        let avoid = if args.is_present("cross") {
+            Avoid::MinimiseCrossings
+        } else {
+            Avoid::Strictly
+        };
+        let max_expansions = match args.value_of("max-expansions") {
+            Some(n) => n.parse()?,
+            None => DEFAULT_MAX_EXPANSIONS,
+        };
+        match route(&wires, target, avoid, moves, max_expansions)? {
+            Some(found) => println!(
+                "route to {:?} ({} steps, {} crossings): {}",
+                target,
+                found.steps,
+                found.crossings,
+                format_wire(&found.spans)
+            ),
+            None => println!("no route to {:?}", target),
         }
     }
-    positions
-}
-
-fn get_positions_set(wire: &Vec<Span>) -> HashSet<Position> {
-    get_positions(wire).into_iter().collect()
-}
-
-fn all_intersections(wires: &Vec<Vec<Span>>) -> HashSet<Position> {
-    let sets: Vec<HashSet<Position>> = wires.iter().map(|wire| get_positions_set(&wire)).collect();
 
-    // Now determine all common points in the sets.
-    let mut common_values = sets[0].clone();
-    for other in &sets[1..] {
-        common_values = common_values
-            .intersection(other)
-            .map(|item| *item) // TODO: Why doesn't into_iter work here?
-            .collect();
+    if args.is_present("ascii") {
+        print!("{}", render_ascii(&wires, 100_000)?);
     }
 
-    common_values
-}
-
-fn closest_intersection(positions: &HashSet<Position>) -> i32 {
-    let mut min: i32 = std::i32::MAX;
-    for pos in positions {
-        let dist = manhattan_dist(pos);
-        if dist < min {
-            min = dist;
-        }
+    if let Some(path) = args.value_of("svg") {
+        fs::write(path, render_svg(&wires, 800, metric)?)?;
     }
-    min
-}
 
-fn manhattan_dist(position: &Position) -> i32 {
-    let (x, y) = position;
-    x.abs() + y.abs()
-}
-
-// Compute the shortest wire length for the given intersection.
-fn closest_length(wires: &Vec<Vec<Span>>, positions: &HashSet<Position>) -> i32 {
-    // Now do the same but based on wire length.
-    let mut min: i32 = std::i32::MAX;
-
-    for pos in positions {
-        let mut dist: i32 = 0;
-        // for each wire, compute the distance to the intersection points. we'll store those.
+    if args.is_present("loops") {
         for (index, wire) in wires.iter().enumerate() {
-            let wire_pos = get_positions(&wire);
-            println!("wire #{} has length {}", index, wire_pos.len());
-
-            // Find the closest intersection distance.
-            if let Some(m) = wire_pos.iter().position(|&item| item == *pos) {
-                let m = m + 1; // Account for zero index.
-                println!("Wire #{}: dist to {:?}: {:?}", index, pos, m);
-                dist += m as i32;
+            for l in loops(wire) {
+                println!(
+                    "wire #{} loops at {:?} from step {} to {} ({} steps)",
+                    index,
+                    l.position,
+                    l.start,
+                    l.end,
+                    l.length()
+                );
             }
-        }
-        if dist < min {
-            min = dist;
+            println!(
+                "wire #{} simplified: {}",
+                index,
+                format_wire(&simplify(wire))
+            );
         }
     }
 
-    min
-}
-
-#[cfg(test)]
-mod test {
-    use super::*;
-
-    #[test]
-    fn parsing() -> Res<()> {
-        let wires = parse_wires("U10,L5,R3\nD5")?;
-        assert_eq!(
-            wires[0],
-            vec![
-                Span {
-                    direction: Direction::Up,
-                    dist: 10
-                },
-                Span {
-                    direction: Direction::Left,
-                    dist: 5
-                },
-                Span {
-                    direction: Direction::Right,
-                    dist: 3
-                }
-            ]
-        );
-        assert_eq!(
-            wires[1],
-            vec![Span {
-                direction: Direction::Down,
-                dist: 5
-            }]
-        );
-        Ok(())
+    if args.is_present("matrix") || args.is_present("shared") {
+        let pairs = pairwise(&wires, algorithm);
+        if args.is_present("matrix") {
+            print!("{}", render_matrix(&pairs, wires.len()));
+        }
+        if let Some(k) = args.value_of("shared") {
+            for crossing in shared_by(&pairs, k.parse()?) {
+                println!("{:?}: wires {:?}", crossing.position, crossing.wires);
+            }
+        }
     }
 
-    #[test]
-    fn positions_up() -> Res<()> {
-        let wire = vec![Span {
-            direction: Direction::Up,
-            dist: 3,
-        }];
-        let positions = get_positions_set(&wire);
-        let expected: HashSet<Position> = vec![(0, -1), (0, -2), (0, -3)].into_iter().collect();
-
-        assert_eq!(positions, expected);
-        Ok(())
-    }
-    #[test]
-    fn positions_down() -> Res<()> {
-        let wire = vec![Span {
-            direction: Direction::Down,
-            dist: 3,
-        }];
-        let positions = get_positions(&wire);
-        let expected = vec![(0, 1), (0, 2), (0, 3)];
+    Ok(())
+}
 
-        assert_eq!(positions, expected);
-        Ok(())
-    }
-    #[test]
-    fn positions_left() -> Res<()> {
-        let wire = vec![Span {
-            direction: Direction::Left,
-            dist: 3,
-        }];
-        let positions = get_positions(&wire);
-        let expected = vec![(-1, 0), (-2, 0), (-3, 0)];
+/// The number of wires, where they intersect and the two answers, as text.
+fn print_answers(wires: &[Vec<Span>], algorithm: Algorithm, metric: Metric) -> Res<()> {
+    println!("Loaded {} wires.", wires.len());
 
-        assert_eq!(positions, expected);
-        Ok(())
-    }
-    #[test]
-    fn positions_right() -> Res<()> {
-        let wire = vec![Span {
-            direction: Direction::Right,
-            dist: 3,
-        }];
-        let positions = get_positions(&wire);
-        let expected = vec![(1, 0), (2, 0), (3, 0)];
+    let intersections = intersections(wires, algorithm);
+    println!("Intersections: {:?}", intersections);
 
-        assert_eq!(positions, expected);
-        Ok(())
+    match closest_position(&intersections, metric)? {
+        Some((_, closest_dist)) => {
+            println!("closest intersection {} dist: {}", metric, closest_dist)
+        }
+        None => println!("no intersections"),
     }
-    #[test]
-    fn positions_left_right() -> Res<()> {
-        let wire = vec![
-            Span {
-                direction: Direction::Right,
-                dist: 3,
-            },
-            Span {
-                direction: Direction::Up,
-                dist: 2,
-            },
-        ];
-        let positions = get_positions(&wire);
-        let expected = vec![(1, 0), (2, 0), (3, 0), (3, -1), (3, -2)];
 
-        assert_eq!(positions, expected);
-        Ok(())
+    if let Some(closest_by_wire_length) = closest_length(wires, &intersections)? {
+        println!("closest by wire length: {}", closest_by_wire_length);
     }
+    Ok(())
+}
 
-    #[test]
-    fn intersections_1() -> Res<()> {
-        let wires = parse_wires("R8,U5,L5,D3\nU7,R6,D4,L4")?;
-        let intersections = all_intersections(&wires);
-        let expected: HashSet<Position> = vec![(3, -3), (6, -5)].into_iter().collect();
-        assert_eq!(intersections, expected);
-        Ok(())
-    }
-    #[test]
-    fn closest_intersection_1() -> Res<()> {
-        let pos: HashSet<Position> = vec![(3, -3), (6, -5)].into_iter().collect();
-        let closest_dist = closest_intersection(&pos);
-        assert_eq!(closest_dist, 6);
-        Ok(())
-    }
-    #[test]
-    fn given_example_1() -> Res<()> {
-        let wires = parse_wires("R8,U5,L5,D3\nU7,R6,D4,L4")?;
-        let intersections = all_intersections(&wires);
-        let expected: HashSet<Position> = vec![(3, -3), (6, -5)].into_iter().collect();
-        assert_eq!(intersections, expected);
-        let closest_by_wire_length = closest_length(&wires, &intersections);
-        assert_eq!(closest_by_wire_length, 30);
-        Ok(())
-    }
-    #[test]
-    fn given_example_2() -> Res<()> {
-        let wires = parse_wires(
-            "R75,D30,R83,U83,L12,D49,R71,U7,L72\nU62,R66,U55,R34,D71,R55,D58,R83",
-        )?;
-        let intersections = all_intersections(&wires);
-        let closest_by_wire_length = closest_length(&wires, &intersections);
-        assert_eq!(closest_by_wire_length, 610);
-        Ok(())
-    }
-    #[test]
-    fn given_example_3() -> Res<()> {
-        let wires = parse_wires(
-            "R98,U47,R26,D63,R33,U87,L62,D20,R33,U53,R51\nU98,R91,D20,R16,D67,R40,U7,R15,U6,R7",
-        )?;
-        let intersections = all_intersections(&wires);
-        let closest_by_wire_length = closest_length(&wires, &intersections);
-        assert_eq!(closest_by_wire_length, 410);
-        Ok(())
+/// `X,Y` on layer 0, or `X,Y,LAYER`.
+fn parse_position(position: &str) -> Res<Position> {
+    match position.split(',').collect::<Vec<_>>()[..] {
+        [x, y] => Ok((x.trim().parse()?, y.trim().parse()?, 0)),
+        [x, y, layer] => Ok((x.trim().parse()?, y.trim().parse()?, layer.trim().parse()?)),
+        _ => error(format!("Invalid position: {}", position)),
     }
 }
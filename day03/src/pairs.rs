@@ -0,0 +1,160 @@
+use crate::sweep::{crossings, Cover};
+use crate::{get_positions_set, Algorithm, Position, Span};
+use std::collections::{BTreeMap, HashSet};
+
+/*
+ * Crossings between any two wires.
+ *
+ * `all_intersections` only keeps points on every wire, which is what the puzzle wants for two
+ * wires but drops most crossings once there are more.
+ */
+
+/// Cells shared by each pair of wires, keyed by the pair's indices, lower first. Every pair
+/// is present, even if its wires never cross.
+pub type Pairs = BTreeMap<(usize, usize), HashSet<Position>>;
+
+/// A cell on more than one wire.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Crossing {
+    pub position: Position,
+    /// Indices of every wire through the cell, in order.
+    pub wires: Vec<usize>,
+}
+
+pub fn pairwise(wires: &[Vec<Span>], algorithm: Algorithm) -> Pairs {
+    let mut pairs = Pairs::new();
+    match algorithm {
+        Algorithm::Raster => {
+            let sets: Vec<HashSet<Position>> =
+                wires.iter().map(|wire| get_positions_set(wire)).collect();
+            for (i, a) in sets.iter().enumerate() {
+                for (j, b) in sets.iter().enumerate().skip(i + 1) {
+                    pairs.insert((i, j), a.intersection(b).copied().collect());
+                }
+            }
+        }
+        Algorithm::Sweep => {
+            let covers: Vec<Cover> = wires.iter().map(|wire| Cover::new(wire)).collect();
+            for (i, a) in covers.iter().enumerate() {
+                for (j, b) in covers.iter().enumerate().skip(i + 1) {
+                    pairs.insert((i, j), crossings(a, b));
+                }
+            }
+        }
+    }
+    pairs
+}
+
+/// Cells on at least `k` wires, and which wires, sorted by position. A cell needs two wires
+/// to be a crossing, so `k` below 2 counts as 2.
+pub fn shared_by(pairs: &Pairs, k: usize) -> Vec<Crossing> {
+    let mut by_position: BTreeMap<Position, Vec<usize>> = BTreeMap::new();
+    for (&(i, j), positions) in pairs {
+        for &position in positions {
+            let wires = by_position.entry(position).or_default();
+            wires.push(i);
+            wires.push(j);
+        }
+    }
+    by_position
+        .into_iter()
+        .map(|(position, mut wires)| {
+            wires.sort_unstable();
+            wires.dedup();
+            Crossing { position, wires }
+        })
+        .filter(|crossing| crossing.wires.len() >= k.max(2))
+        .collect()
+}
+
+/// Number of crossings between each pair of `count` wires, as a table with a `-` for each
+/// wire against itself.
+pub fn render_matrix(pairs: &Pairs, count: usize) -> String {
+    let cell = |i: usize, j: usize| match (i, j) {
+        _ if i == j => "-".to_string(),
+        _ => pairs
+            .get(&(i.min(j), i.max(j)))
+            .map_or(0, HashSet::len)
+            .to_string(),
+    };
+    let width = (0..count)
+        .flat_map(|i| (0..count).map(move |j| (i, j)))
+        .map(|(i, j)| cell(i, j).len())
+        .chain(std::iter::once(count.saturating_sub(1).to_string().len()))
+        .max()
+        .unwrap_or(1);
+
+    let mut out = format!("{:>width$}", "", width = width);
+    for j in 0..count {
+        out.push_str(&format!(" {:>width$}", j, width = width));
+    }
+    out.push('\n');
+    for i in 0..count {
+        out.push_str(&format!("{:>width$}", i, width = width));
+        for j in 0..count {
+            out.push_str(&format!(" {:>width$}", cell(i, j), width = width));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse_wires;
+    use common::Res;
+
+    // Wire 2 crosses both others, which never cross each other.
+    const WIRES: &str = "R4\nU2,R4\nR2,U3";
+
+    #[test]
+    fn pairs() -> Res<()> {
+        let wires = parse_wires(WIRES)?;
+        let pairs = pairwise(&wires, Algorithm::Sweep);
+        assert_eq!(pairs, pairwise(&wires, Algorithm::Raster));
+        assert_eq!(pairs.len(), 3);
+        assert!(pairs[&(0, 1)].is_empty());
+        assert_eq!(
+            pairs[&(0, 2)],
+            vec![(1, 0, 0), (2, 0, 0)].into_iter().collect()
+        );
+        assert_eq!(pairs[&(1, 2)], vec![(2, -2, 0)].into_iter().collect());
+        // Nothing is on every wire.
+        assert!(crate::all_intersections(&wires).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn shared() -> Res<()> {
+        // Wire 3 runs along part of wire 2.
+        let wires = parse_wires(format!("{}\nR2,U2", WIRES))?;
+        let pairs = pairwise(&wires, Algorithm::Sweep);
+        let crossing = |x, y, wires: &[usize]| Crossing {
+            position: (x, y, 0),
+            wires: wires.to_vec(),
+        };
+        assert_eq!(
+            shared_by(&pairs, 3),
+            vec![
+                crossing(1, 0, &[0, 2, 3]),
+                crossing(2, -2, &[1, 2, 3]),
+                crossing(2, 0, &[0, 2, 3]),
+            ]
+        );
+        assert_eq!(shared_by(&pairs, 0).len(), 4);
+        assert!(shared_by(&pairs, 4).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn matrix() -> Res<()> {
+        let wires = parse_wires(WIRES)?;
+        let pairs = pairwise(&wires, Algorithm::Sweep);
+        assert_eq!(
+            render_matrix(&pairs, 3),
+            "  0 1 2\n0 - 0 2\n1 0 - 1\n2 2 1 -\n"
+        );
+        Ok(())
+    }
+}
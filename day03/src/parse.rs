@@ -0,0 +1,192 @@
+use common::{error, Res};
+use std::collections::HashMap;
+
+/*
+ * The wire file format.
+ *
+ * One wire per line, as comma-separated spans, optionally named with a prefix like `A:`.
+ * Whitespace around names and spans is ignored, as are blank lines, `\r` before line ends and
+ * anything after a `#`:
+ *
+ *     # The first example.
+ *     A: R8, U5, L5, D3
+ *     B: U7, R6, D4, L4
+ *
+ * Distances are plain digits, so can't be negative. A distance of zero is allowed, and moves
+ * nowhere.
+ *
+ * Errors give the line and column, 1-based, with the wire and span, 0-based as everywhere
+ * else.
+ */
+
+/// A wire as written in the input.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WireDef<T> {
+    pub name: Option<String>,
+    pub spans: Vec<T>,
+    /// Line of the input it's on, from 1.
+    pub line: usize,
+}
+
+/// Every wire in the input, with each span read by `parse_span` and each whole wire then
+/// checked by `check`.
+pub fn parse_defs<T, P, C>(contents: &str, parse_span: P, check: C) -> Res<Vec<WireDef<T>>>
+where
+    P: Fn(&str) -> Res<T>,
+    C: Fn(&[T]) -> Res<()>,
+{
+    let mut defs: Vec<WireDef<T>> = vec![];
+    // Lines each name is first used on.
+    let mut names: HashMap<String, usize> = HashMap::new();
+    for (index, line) in contents.split('\n').enumerate() {
+        let line_number = index + 1;
+        let wire = defs.len();
+        let column = |offset: usize| line[..offset].chars().count() + 1;
+        let fail = |offset: usize, message: String| {
+            error(format!(
+                "Line {}, column {} (wire #{}): {}",
+                line_number,
+                column(offset),
+                wire,
+                message
+            ))
+        };
+
+        let text = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+        if text.trim().is_empty() {
+            continue;
+        }
+
+        let (name, mut offset, body) = match text.find(':') {
+            Some(colon) => {
+                let name = text[..colon].trim();
+                let start = text.len() - text.trim_start().len();
+                if name.is_empty()
+                    || !name
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+                {
+                    return fail(start, format!("Invalid wire name: {:?}", name));
+                }
+                if let Some(first) = names.get(name) {
+                    return fail(
+                        start,
+                        format!("Wire name {} already used on line {}", name, first),
+                    );
+                }
+                names.insert(name.to_string(), line_number);
+                (Some(name.to_string()), colon + 1, &text[colon + 1..])
+            }
+            None => (None, 0, text),
+        };
+
+        let mut spans = vec![];
+        for piece in body.split(',') {
+            let span_str = piece.trim();
+            let start = offset + (piece.len() - piece.trim_start().len());
+            offset += piece.len() + 1;
+            if span_str.is_empty() {
+                // Allows a trailing comma.
+                if offset > text.len() && !spans.is_empty() {
+                    continue;
+                }
+                return fail(start, format!("Missing span {}", spans.len()));
+            }
+            match parse_span(span_str) {
+                Ok(span) => spans.push(span),
+                Err(e) => return fail(start, format!("Span {}: {}", spans.len(), e)),
+            }
+        }
+        if let Err(e) = check(&spans) {
+            return error(format!("Line {} (wire #{}): {}", line_number, wire, e));
+        }
+        defs.push(WireDef {
+            name,
+            spans,
+            line: line_number,
+        });
+    }
+    Ok(defs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parse_wire_defs, parse_wires, Span};
+
+    #[test]
+    fn format() -> Res<()> {
+        let plain = parse_wires("R8,U5,L5,D3\nU7,R6,D4,L4")?;
+        let input = "# The first example.\r\n\
+                     \r\n\
+                     A: R8, U5 ,L5,D3 # wire A\r\n\
+                     \t B:U7,R6,D4,L4,\r\n\
+                     \r\n";
+        assert_eq!(parse_wires(input)?, plain);
+        let defs = parse_wire_defs(input)?;
+        assert_eq!(defs[0].name.as_deref(), Some("A"));
+        assert_eq!(defs[1].name.as_deref(), Some("B"));
+        assert_eq!(defs[1].line, 4);
+        assert_eq!(parse_wire_defs("R1\nwire_2: U1")?[0].name, None);
+        assert!(parse_wires("# Nothing here.\n\n")?.is_empty());
+        Ok(())
+    }
+
+    fn message(input: &str) -> String {
+        parse_wires(input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            message("R8,U5\nU7, R6,X4"),
+            "Line 2, column 8 (wire #1): Span 2: Invalid direction: X"
+        );
+        assert_eq!(
+            message("# header\n\nA: R8,\tU5x"),
+            "Line 3, column 8 (wire #0): Span 1: Invalid distance in U5x"
+        );
+        assert_eq!(
+            message("R8,,U5"),
+            "Line 1, column 4 (wire #0): Missing span 1"
+        );
+        assert_eq!(message("A:"), "Line 1, column 3 (wire #0): Missing span 0");
+        assert_eq!(
+            message("A: R1\n  A: U1"),
+            "Line 2, column 3 (wire #1): Wire name A already used on line 1"
+        );
+        assert_eq!(
+            message(" : R1"),
+            "Line 1, column 2 (wire #0): Invalid wire name: \"\""
+        );
+        assert_eq!(
+            message("R1\nR1,R9223372036854775807"),
+            "Line 2 (wire #1): Span 1 (R9223372036854775807) overflows the coordinates"
+        );
+        // Columns count characters, not bytes.
+        assert_eq!(
+            message("é: R1,Ü2"),
+            "Line 1, column 7 (wire #0): Span 1: Invalid direction: Ü"
+        );
+    }
+
+    #[test]
+    fn distances() -> Res<()> {
+        assert_eq!("R0".parse::<Span>()?.dist, 0);
+        assert_eq!(
+            message("L-3"),
+            "Line 1, column 1 (wire #0): Span 0: Distance in L-3 is negative"
+        );
+        assert_eq!(
+            message("D99999999999999999999"),
+            "Line 1, column 1 (wire #0): Span 0: Distance in D99999999999999999999 is too large"
+        );
+        assert!("R+3".parse::<Span>().is_err());
+        assert!("R".parse::<Span>().is_err());
+        assert!("".parse::<Span>().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,308 @@
+use crate::parse::WireDef;
+use crate::{
+    closest_position, corners, delays, intersections, Algorithm, Distance, Metric, Position, Span,
+};
+use common::Res;
+use std::fmt;
+
+/*
+ * Results as JSON, for other tools to read.
+ *
+ * There's no JSON library to hand, so the document is built as a `Json` value and written out
+ * here. It looks like:
+ *
+ *     {
+ *       "wires": [
+ *         {
+ *           "name": "A",
+ *           "length": 21,
+ *           "bounding_box": {
+ *             "min": {"x": 0, "y": -5, "layer": 0},
+ *             "max": {"x": 8, "y": 0, "layer": 0}
+ *           }
+ *         },
+ *         ...
+ *       ],
+ *       "intersections": [
+ *         {
+ *           "position": {"x": 3, "y": -3, "layer": 0},
+ *           "manhattan_distance": 6,
+ *           "steps": [20, 20],
+ *           "total_steps": 40
+ *         },
+ *         ...
+ *       ],
+ *       "closest_manhattan_distance": 6,
+ *       "closest_total_steps": 30
+ *     }
+ *
+ * Distances are by the metric asked for, which names their keys, e.g. `euclidean_distance`.
+ * Bounding boxes include the origin. Names are null for unnamed wires, and the two answers
+ * are null if nothing intersects. Intersections are sorted by position, x first.
+ */
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Int(i128),
+    Float(f64),
+    Str(String),
+    Array(Vec<Json>),
+    /// Members in the order they're written.
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn object(members: Vec<(&str, Json)>) -> Json {
+        Json::Object(
+            members
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    fn position((x, y, layer): Position) -> Json {
+        Json::object(vec![
+            ("x", Json::Int(x.into())),
+            ("y", Json::Int(y.into())),
+            ("layer", Json::Int(layer.into())),
+        ])
+    }
+
+    fn distance(distance: Distance) -> Json {
+        match distance {
+            Distance::Steps(steps) => Json::Int(steps.into()),
+            Distance::Straight(dist) => Json::Float(dist),
+        }
+    }
+
+    fn is_empty_or_scalar(&self) -> bool {
+        match self {
+            Json::Array(items) => items.is_empty(),
+            Json::Object(members) => members.is_empty(),
+            _ => true,
+        }
+    }
+
+    /// Containers holding only scalars or empty containers go on one line, anything else one
+    /// member per line.
+    fn write(&self, f: &mut fmt::Formatter, indent: usize) -> fmt::Result {
+        let (open, close, members): (char, char, Vec<(Option<&str>, &Json)>) = match self {
+            Json::Null => return write!(f, "null"),
+            Json::Int(n) => return write!(f, "{}", n),
+            // Distances are never infinite or NaN, which JSON can't hold.
+            Json::Float(n) => return write!(f, "{}", n),
+            Json::Str(s) => return write_string(f, s),
+            Json::Array(items) => ('[', ']', items.iter().map(|item| (None, item)).collect()),
+            Json::Object(members) => (
+                '{',
+                '}',
+                members
+                    .iter()
+                    .map(|(key, value)| (Some(key.as_str()), value))
+                    .collect(),
+            ),
+        };
+        let inline = members.iter().all(|(_, value)| value.is_empty_or_scalar());
+        write!(f, "{}", open)?;
+        for (index, (key, value)) in members.iter().enumerate() {
+            if index > 0 {
+                write!(f, ",")?;
+            }
+            if inline {
+                if index > 0 {
+                    write!(f, " ")?;
+                }
+            } else {
+                write!(f, "\n{:width$}", "", width = indent + 2)?;
+            }
+            if let Some(key) = key {
+                write_string(f, key)?;
+                write!(f, ": ")?;
+            }
+            value.write(f, indent + 2)?;
+        }
+        if !inline && !members.is_empty() {
+            write!(f, "\n{:width$}", "", width = indent)?;
+        }
+        write!(f, "{}", close)
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+/// The report on the wires, with intersections found by the given algorithm and distances by
+/// the metric.
+pub fn report(defs: &[WireDef<Span>], algorithm: Algorithm, metric: Metric) -> Res<Json> {
+    let wires: Vec<Vec<Span>> = defs.iter().map(|def| def.spans.clone()).collect();
+
+    let mut wire_reports = vec![];
+    for def in defs {
+        let corners = corners(&def.spans)?;
+        let min = corners.iter().fold(corners[0], |m, p| {
+            (m.0.min(p.0), m.1.min(p.1), m.2.min(p.2))
+        });
+        let max = corners.iter().fold(corners[0], |m, p| {
+            (m.0.max(p.0), m.1.max(p.1), m.2.max(p.2))
+        });
+        // Widened, as a wire can double back further than a coordinate reaches.
+        let length: i128 = def.spans.iter().map(|span| i128::from(span.dist)).sum();
+        wire_reports.push(Json::object(vec![
+            ("name", def.name.clone().map_or(Json::Null, Json::Str)),
+            ("length", Json::Int(length)),
+            (
+                "bounding_box",
+                Json::object(vec![
+                    ("min", Json::position(min)),
+                    ("max", Json::position(max)),
+                ]),
+            ),
+        ]));
+    }
+
+    let intersections = intersections(&wires, algorithm);
+    let delays = delays(&wires, &intersections)?;
+    let distance_key = format!("{}_distance", metric);
+    let mut intersection_reports = vec![];
+    for delay in &delays {
+        intersection_reports.push(Json::object(vec![
+            ("position", Json::position(delay.position)),
+            (
+                distance_key.as_str(),
+                Json::distance(metric.distance(delay.position)?),
+            ),
+            (
+                "steps",
+                Json::Array(delay.steps.iter().map(|&s| Json::Int(s as i128)).collect()),
+            ),
+            ("total_steps", Json::Int(delay.total as i128)),
+        ]));
+    }
+    let closest_key = format!("closest_{}", distance_key);
+    let closest_dist = closest_position(&intersections, metric)?;
+    let closest_steps = delays.iter().map(|delay| delay.total).min();
+
+    Ok(Json::object(vec![
+        ("wires", Json::Array(wire_reports)),
+        ("intersections", Json::Array(intersection_reports)),
+        (
+            closest_key.as_str(),
+            closest_dist.map_or(Json::Null, |(_, dist)| Json::distance(dist)),
+        ),
+        (
+            "closest_total_steps",
+            closest_steps.map_or(Json::Null, |steps| Json::Int(steps as i128)),
+        ),
+    ]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parse_wire_defs, Moves};
+
+    #[test]
+    fn given_example() -> Res<()> {
+        let defs = parse_wire_defs("A: R8,U5,L5,D3\nU7,R6,D4,L4")?;
+        let expected = r#"{
+  "wires": [
+    {
+      "name": "A",
+      "length": 21,
+      "bounding_box": {
+        "min": {"x": 0, "y": -5, "layer": 0},
+        "max": {"x": 8, "y": 0, "layer": 0}
+      }
+    },
+    {
+      "name": null,
+      "length": 21,
+      "bounding_box": {
+        "min": {"x": 0, "y": -7, "layer": 0},
+        "max": {"x": 6, "y": 0, "layer": 0}
+      }
+    }
+  ],
+  "intersections": [
+    {
+      "position": {"x": 3, "y": -3, "layer": 0},
+      "manhattan_distance": 6,
+      "steps": [20, 20],
+      "total_steps": 40
+    },
+    {
+      "position": {"x": 6, "y": -5, "layer": 0},
+      "manhattan_distance": 11,
+      "steps": [15, 15],
+      "total_steps": 30
+    }
+  ],
+  "closest_manhattan_distance": 6,
+  "closest_total_steps": 30
+}"#;
+        for &algorithm in &[Algorithm::Raster, Algorithm::Sweep] {
+            assert_eq!(
+                report(&defs, algorithm, Metric::Manhattan)?.to_string(),
+                expected
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn no_intersections() -> Res<()> {
+        let json = report(
+            &parse_wire_defs("R5\nL5")?,
+            Algorithm::Sweep,
+            Metric::Manhattan,
+        )?
+        .to_string();
+        assert!(json.contains("\"intersections\": [],"));
+        assert!(json.contains("\"closest_manhattan_distance\": null,"));
+        assert!(json.ends_with("\"closest_total_steps\": null\n}"));
+        assert_eq!(
+            report(&[], Algorithm::Sweep, Metric::Manhattan)?.to_string(),
+            "{\"wires\": [], \"intersections\": [], \"closest_manhattan_distance\": null, \
+             \"closest_total_steps\": null}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn other_moves_and_metrics() -> Res<()> {
+        let defs = Moves::ALL.parse_wire_defs("A1,R3,DR1\nR4,A1,D1")?;
+        let json = report(&defs, Algorithm::Sweep, Metric::Euclidean)?.to_string();
+        assert!(json.contains("\"max\": {\"x\": 4, \"y\": 1, \"layer\": 1}"));
+        assert!(json.contains("\"position\": {\"x\": 4, \"y\": 1, \"layer\": 1},"));
+        assert!(json.contains("\"euclidean_distance\": 4.242640687119285,"));
+        assert!(json.contains("\"closest_euclidean_distance\": 4.242640687119285,"));
+        let json = report(&defs, Algorithm::Raster, Metric::Chebyshev)?.to_string();
+        assert!(json.contains("\"chebyshev_distance\": 4,"));
+        assert!(json.contains("\"closest_chebyshev_distance\": 4,"));
+        Ok(())
+    }
+
+    #[test]
+    fn strings() {
+        let json = Json::Array(vec![Json::Str("a \"b\" \\ c\n".to_string())]);
+        assert_eq!(json.to_string(), r#"["a \"b\" \\ c\u000a"]"#);
+    }
+}
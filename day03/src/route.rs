@@ -0,0 +1,291 @@
+use crate::{from_path, get_positions_set, Moves, Position, Span};
+use common::Res;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::error::Error;
+use std::fmt;
+
+/*
+ * Routing a new wire from the origin around the existing ones.
+ *
+ * A* over the grid, making the moves the new wire is allowed. Cells on existing wires are
+ * either walls, or cost a crossing per wire on them, with fewer crossings always beating a
+ * shorter route. The search stays within one cell of the box around the wires and the target:
+ * any route leaving that box could follow its edge instead, which is empty and no longer.
+ *
+ * Wires far apart make that box huge, so the search gives up with `ExpansionLimit` once it
+ * has expanded a given number of cells, rather than run out of memory.
+ */
+
+/// Cells `route` expands before giving up, unless another limit is given.
+pub const DEFAULT_MAX_EXPANSIONS: usize = 10_000_000;
+
+/// The search expanded `limit` cells without finding a route or ruling one out.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ExpansionLimit {
+    pub limit: usize,
+}
+
+impl fmt::Display for ExpansionLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "No route found within {} expanded cells", self.limit)
+    }
+}
+
+impl Error for ExpansionLimit {}
+
+/// How to treat existing wires.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Avoid {
+    /// Never touch them.
+    Strictly,
+    /// Cross as few as possible.
+    MinimiseCrossings,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Route {
+    pub spans: Vec<Span>,
+    pub steps: usize,
+    /// Wire cells passed through, counting each wire on a cell.
+    pub crossings: usize,
+}
+
+/// The shortest route from the origin to the target using the moves, or `None` if there
+/// isn't one. Fails with `ExpansionLimit` after expanding `max_expansions` cells.
+pub fn route(
+    wires: &[Vec<Span>],
+    target: Position,
+    avoid: Avoid,
+    moves: Moves,
+    max_expansions: usize,
+) -> Res<Option<Route>> {
+    let mut occupied: HashMap<Position, usize> = HashMap::new();
+    for wire in wires {
+        for position in get_positions_set(wire) {
+            *occupied.entry(position).or_default() += 1;
+        }
+    }
+
+    let mut min = (target.0.min(0), target.1.min(0), target.2.min(0));
+    let mut max = (target.0.max(0), target.1.max(0), target.2.max(0));
+    for &(x, y, layer) in occupied.keys() {
+        min = (min.0.min(x), min.1.min(y), min.2.min(layer));
+        max = (max.0.max(x), max.1.max(y), max.2.max(layer));
+    }
+    // At the edges of the coordinates, the box just stops there.
+    let (min, max) = (
+        (
+            min.0.saturating_sub(1),
+            min.1.saturating_sub(1),
+            min.2.saturating_sub(1),
+        ),
+        (
+            max.0.saturating_add(1),
+            max.1.saturating_add(1),
+            max.2.saturating_add(1),
+        ),
+    );
+    let inside = |(x, y, layer): Position| {
+        (min.0..=max.0).contains(&x)
+            && (min.1..=max.1).contains(&y)
+            && (min.2..=max.2).contains(&layer)
+    };
+    // A diagonal move covers a step along both x and y at once.
+    let remaining = |(x, y, layer): Position| {
+        let (dx, dy) = (target.0.abs_diff(x), target.1.abs_diff(y));
+        let flat = if moves.diagonal {
+            dx.max(dy)
+        } else {
+            dx.saturating_add(dy)
+        };
+        flat.saturating_add(target.2.abs_diff(layer)) as usize
+    };
+    let directions = moves.directions();
+
+    // Costs are (crossings, steps), compared in that order.
+    let mut best: HashMap<Position, (usize, usize)> = HashMap::new();
+    let mut came_from: HashMap<Position, Position> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    let origin = (0, 0, 0);
+    best.insert(origin, (0, 0));
+    queue.push(Reverse(((0, remaining(origin)), (0, 0), origin)));
+    let mut expanded = 0;
+    while let Some(Reverse((_, cost, position))) = queue.pop() {
+        if position == target {
+            let mut path = vec![position];
+            while let Some(&previous) = came_from.get(path.last().unwrap()) {
+                path.push(previous);
+            }
+            path.reverse();
+            return Ok(Some(Route {
+                spans: from_path(&path),
+                steps: cost.1,
+                crossings: cost.0,
+            }));
+        }
+        if best.get(&position).is_some_and(|&b| b < cost) {
+            continue;
+        }
+        if expanded == max_expansions {
+            return Err(Box::new(ExpansionLimit {
+                limit: max_expansions,
+            }));
+        }
+        expanded += 1;
+        for &direction in &directions {
+            let next = match direction.checked_step(position) {
+                Some(next) if inside(next) => next,
+                _ => continue,
+            };
+            let wires_here = occupied.get(&next).cloned().unwrap_or(0);
+            if wires_here > 0 && avoid == Avoid::Strictly {
+                continue;
+            }
+            let next_cost = (cost.0 + wires_here, cost.1 + 1);
+            if best.get(&next).is_some_and(|&b| b <= next_cost) {
+                continue;
+            }
+            best.insert(next, next_cost);
+            came_from.insert(next, position);
+            let estimate = (next_cost.0, next_cost.1 + remaining(next));
+            queue.push(Reverse((estimate, next_cost, next)));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{format_wire, get_positions, parse_wires, Coord};
+    use common::Res;
+
+    const WIRES: &str = "R8,U5,L5,D3\nU7,R6,D4,L4";
+
+    fn find(
+        wires: &[Vec<Span>],
+        target: Position,
+        avoid: Avoid,
+        moves: Moves,
+    ) -> Res<Option<Route>> {
+        route(wires, target, avoid, moves, DEFAULT_MAX_EXPANSIONS)
+    }
+
+    /// Check the route reaches the target and touches no more wire cells than it says.
+    fn check(wires: &[Vec<Span>], target: Position, route: &Route) {
+        let path = get_positions(&route.spans);
+        assert_eq!(path.last(), Some(&target));
+        assert_eq!(path.len(), route.steps);
+        let sets: Vec<_> = wires.iter().map(|wire| get_positions_set(wire)).collect();
+        let touched: usize = path
+            .iter()
+            .map(|p| sets.iter().filter(|set| set.contains(p)).count())
+            .sum();
+        assert_eq!(touched, route.crossings);
+    }
+
+    #[test]
+    fn around_wires() -> Res<()> {
+        let wires = parse_wires(WIRES)?;
+        let found = find(&wires, (-2, -3, 0), Avoid::Strictly, Moves::FOUR_WAY)?.unwrap();
+        check(&wires, (-2, -3, 0), &found);
+        assert_eq!((found.steps, found.crossings), (5, 0));
+
+        // Both neighbours towards the target are on wires, so it goes round.
+        let found = find(&wires, (10, -3, 0), Avoid::Strictly, Moves::FOUR_WAY)?.unwrap();
+        check(&wires, (10, -3, 0), &found);
+        assert_eq!((found.steps, found.crossings), (15, 0));
+        // Printed in the input format.
+        assert_eq!(parse_wires(format_wire(&found.spans))?, vec![found.spans]);
+        Ok(())
+    }
+
+    #[test]
+    fn enclosed() -> Res<()> {
+        let wires = parse_wires(WIRES)?;
+        // Walled in by both wires.
+        assert_eq!(
+            find(&wires, (5, -4, 0), Avoid::Strictly, Moves::FOUR_WAY)?,
+            None
+        );
+        let found = find(
+            &wires,
+            (5, -4, 0),
+            Avoid::MinimiseCrossings,
+            Moves::FOUR_WAY,
+        )?
+        .unwrap();
+        check(&wires, (5, -4, 0), &found);
+        assert_eq!((found.steps, found.crossings), (9, 2));
+        // On a wire.
+        assert_eq!(
+            find(&wires, (8, 0, 0), Avoid::Strictly, Moves::FOUR_WAY)?,
+            None
+        );
+        assert_eq!(
+            find(&wires, (0, 0, 0), Avoid::Strictly, Moves::FOUR_WAY)?
+                .unwrap()
+                .steps,
+            0
+        );
+
+        // Targets at the edges of the coordinates, from an origin boxed in by a wire.
+        let ring = parse_wires("R1,U1,L2,D2,R2")?;
+        for &target in &[(Coord::MAX, Coord::MIN, 0), (Coord::MIN, Coord::MAX, 0)] {
+            assert_eq!(find(&ring, target, Avoid::Strictly, Moves::FOUR_WAY)?, None);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn expansion_limit() -> Res<()> {
+        let wires = parse_wires(WIRES)?;
+        let limited = |max_expansions| {
+            route(
+                &wires,
+                (10, -3, 0),
+                Avoid::Strictly,
+                Moves::FOUR_WAY,
+                max_expansions,
+            )
+        };
+        let error = limited(10).unwrap_err();
+        assert_eq!(
+            *error.downcast::<ExpansionLimit>().unwrap(),
+            ExpansionLimit { limit: 10 }
+        );
+        assert_eq!(limited(1000)?.unwrap().steps, 15);
+
+        // Wires far apart leave a box too big to search, even though the target is close.
+        let far = parse_wires("R100000,U100000")?;
+        let found = route(&far, (-1, -1, 0), Avoid::Strictly, Moves::FOUR_WAY, 100)?;
+        assert_eq!(found.unwrap().steps, 2);
+        // Boxed in by a ring with a million cells inside, which would all need expanding.
+        let ring = parse_wires("D1,R500,U1000,L1000,D1000,R499")?;
+        assert!(route(&ring, (-600, 0, 0), Avoid::Strictly, Moves::FOUR_WAY, 1000).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn other_moves() -> Res<()> {
+        let wires = parse_wires(WIRES)?;
+        let found = find(&wires, (-2, -3, 0), Avoid::Strictly, Moves::EIGHT_WAY)?.unwrap();
+        check(&wires, (-2, -3, 0), &found);
+        assert_eq!((found.steps, found.crossings), (3, 0));
+        // Diagonals can't slip between the wires' corners, but a route can go over them.
+        assert_eq!(
+            find(&wires, (5, -4, 0), Avoid::Strictly, Moves::EIGHT_WAY)?,
+            None
+        );
+        let found = find(&wires, (5, -4, 0), Avoid::Strictly, Moves::LAYERED)?.unwrap();
+        check(&wires, (5, -4, 0), &found);
+        assert_eq!((found.steps, found.crossings), (11, 0));
+        // Without layer moves, other layers are out of reach.
+        assert_eq!(
+            find(&wires, (1, 1, 1), Avoid::Strictly, Moves::EIGHT_WAY)?,
+            None
+        );
+        Ok(())
+    }
+}
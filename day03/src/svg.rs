@@ -0,0 +1,190 @@
+use crate::pairs::{pairwise, shared_by};
+use crate::{
+    closest_delay, closest_position, corners, sweep, Algorithm, Coord, Metric, Position, Span,
+};
+use common::Res;
+
+/*
+ * Wire layouts as SVG.
+ *
+ * Each wire is a polyline through its corners. Circles mark the origin, every cell where two
+ * or more wires meet, and the closest intersections by the chosen metric and by delay.
+ * Coordinates are scaled so the larger side of the layout fits the requested size. Layers are
+ * drawn on top of each other, seen from above, with each cell's layer in its title.
+ */
+
+const COLOURS: [&str; 10] = [
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#7f7f7f",
+    "#bcbd22", "#17becf",
+];
+
+/// Pixels kept clear around the layout, so markers at the edges aren't cut off.
+const MARGIN: f64 = 10.0;
+
+/// Maps grid positions onto the image.
+struct Scale {
+    min: (Coord, Coord),
+    factor: f64,
+}
+
+impl Scale {
+    fn new(positions: &[Position], size: u32) -> Scale {
+        let min_x = positions.iter().map(|p| p.0).min().unwrap_or(0);
+        let max_x = positions.iter().map(|p| p.0).max().unwrap_or(0);
+        let min_y = positions.iter().map(|p| p.1).min().unwrap_or(0);
+        let max_y = positions.iter().map(|p| p.1).max().unwrap_or(0);
+        // Widths as floats, as the span of two coordinates can overflow.
+        let extent = (max_x as f64 - min_x as f64).max(max_y as f64 - min_y as f64);
+        let room = (f64::from(size) - 2.0 * MARGIN).max(1.0);
+        Scale {
+            min: (min_x, min_y),
+            factor: room / extent.max(1.0),
+        }
+    }
+
+    fn point(&self, (x, y, _): Position) -> (f64, f64) {
+        (
+            MARGIN + (x as f64 - self.min.0 as f64) * self.factor,
+            MARGIN + (y as f64 - self.min.1 as f64) * self.factor,
+        )
+    }
+
+    fn circle(&self, position: Position, radius: f64, style: &str, title: &str) -> String {
+        let (cx, cy) = self.point(position);
+        format!(
+            "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"{}\" {}><title>{} {:?}</title></circle>\n",
+            cx, cy, radius, style, title, position
+        )
+    }
+}
+
+/// The wires drawn as an SVG image whose larger side is `size` pixels, marking the closest
+/// intersection by the metric.
+pub fn render_svg(wires: &[Vec<Span>], size: u32, metric: Metric) -> Res<String> {
+    let paths: Vec<Vec<Position>> = wires.iter().map(|wire| corners(wire)).collect::<Res<_>>()?;
+    let all: Vec<Position> = paths.iter().flatten().cloned().collect();
+    let scale = Scale::new(&all, size);
+    let (right, bottom) = scale.point((
+        all.iter().map(|p| p.0).max().unwrap_or(0),
+        all.iter().map(|p| p.1).max().unwrap_or(0),
+        0,
+    ));
+    let (width, height) = (right + MARGIN, bottom + MARGIN);
+
+    let mut out = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.0}\" height=\"{:.0}\" viewBox=\"0 0 {:.0} {:.0}\">\n",
+        width, height, width, height
+    );
+    out.push_str("<rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n");
+
+    for (index, path) in paths.iter().enumerate() {
+        let points: Vec<String> = path
+            .iter()
+            .map(|&position| {
+                let (x, y) = scale.point(position);
+                format!("{:.1},{:.1}", x, y)
+            })
+            .collect();
+        out.push_str(&format!(
+            "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\" points=\"{}\"><title>wire #{}</title></polyline>\n",
+            COLOURS[index % COLOURS.len()],
+            points.join(" "),
+            index
+        ));
+    }
+
+    let crossings = shared_by(&pairwise(wires, Algorithm::Sweep), 2);
+    for crossing in &crossings {
+        let title = format!("wires {:?} at", crossing.wires);
+        out.push_str(&scale.circle(
+            crossing.position,
+            2.5,
+            "fill=\"none\" stroke=\"black\"",
+            &title,
+        ));
+    }
+    out.push_str(&scale.circle((0, 0, 0), 4.0, "fill=\"black\"", "origin"));
+
+    let intersections = sweep::all_intersections(wires);
+    if let Some((position, _)) = closest_position(&intersections, metric)? {
+        out.push_str(&scale.circle(
+            position,
+            7.0,
+            "fill=\"none\" stroke=\"#d62728\" stroke-width=\"2\"",
+            &format!("closest by {} distance", metric),
+        ));
+    }
+    if let Some(delay) = closest_delay(wires, &intersections)? {
+        out.push_str(&scale.circle(
+            delay.position,
+            10.0,
+            "fill=\"none\" stroke=\"#2ca02c\" stroke-width=\"2\" stroke-dasharray=\"3,2\"",
+            "closest by delay",
+        ));
+    }
+
+    out.push_str("</svg>\n");
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parse_wires, Moves};
+
+    /// Every circle centre and polyline point coordinate.
+    fn coordinates(svg: &str) -> Vec<f64> {
+        let mut values = vec![];
+        for attribute in &["cx=\"", "cy=\"", "points=\""] {
+            for rest in svg.split(attribute).skip(1) {
+                let value = &rest[..rest.find('"').unwrap()];
+                values.extend(value.split([' ', ',']).map(|v| v.parse::<f64>().unwrap()));
+            }
+        }
+        values
+    }
+
+    #[test]
+    fn example() -> Res<()> {
+        let svg = render_svg(
+            &parse_wires("R8,U5,L5,D3\nU7,R6,D4,L4")?,
+            200,
+            Metric::Manhattan,
+        )?;
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"200\""));
+        assert!(svg.ends_with("</svg>\n"));
+        assert_eq!(svg.matches("<polyline").count(), 2);
+        assert!(svg.contains("stroke=\"#1f77b4\""));
+        assert!(svg.contains("stroke=\"#ff7f0e\""));
+        // Two intersections, the origin and the two closest.
+        assert_eq!(svg.matches("<circle").count(), 5);
+        assert!(svg.contains("<title>closest by manhattan distance (3, -3, 0)</title>"));
+        assert!(svg.contains("<title>closest by delay (6, -5, 0)</title>"));
+        assert!(svg.contains("<title>origin (0, 0, 0)</title>"));
+        Ok(())
+    }
+
+    #[test]
+    fn other_moves_and_metrics() -> Res<()> {
+        let wires = Moves::ALL.parse_wires("A1,R3,DR1,L4\nR4,A1,D1,L4")?;
+        let svg = render_svg(&wires, 200, Metric::Chebyshev)?;
+        assert_eq!(svg.matches("<polyline").count(), 2);
+        assert!(svg.contains("<title>closest by chebyshev distance (0, 1, 1)</title>"));
+        assert!(svg.contains("<title>closest by delay (4, 1, 1)</title>"));
+        Ok(())
+    }
+
+    #[test]
+    fn scales_to_fit() -> Res<()> {
+        let svg = render_svg(
+            &parse_wires("R9000000000000000000,U5\nU9000000000000000000,R5")?,
+            400,
+            Metric::Manhattan,
+        )?;
+        assert!(svg.contains("width=\"400\""));
+        let values = coordinates(&svg);
+        assert!(!values.is_empty());
+        assert!(values.iter().all(|&v| (0.0..=400.0).contains(&v)));
+        Ok(())
+    }
+}
@@ -0,0 +1,362 @@
+use crate::line::{segments, Line, AXES};
+use crate::{Position, Span};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/*
+ * Segment based intersections.
+ *
+ * Each span covers a run of cells along a line (see `line`), so a wire is stored as the
+ * intervals it covers on each line, merged so they don't overlap. Runs where both wires share
+ * a line come from intersecting those lines' interval lists directly.
+ *
+ * Lines along two different axes cross at most once, and only if they lie in the same plane.
+ * For each pair of axes, cells get three coordinates: which plane they're in, a row that's the
+ * same all along the first axis, and a sweep coordinate that's the same all along the second.
+ * Lines along the first axis are then intervals of the sweep coordinate in one row, and a
+ * sweep across finds the rows each line along the second axis spans while they're active. For
+ * rows and columns these are just the layer, y and x. Two diagonals can cross between cells,
+ * like the strokes of an X drawn on a 2x2 square, so each crossing is checked against both
+ * lines before it counts.
+ */
+
+type Interval = (i128, i128);
+
+type Vector = [i128; 3];
+
+fn vector((x, y, layer): Position) -> Vector {
+    [x.into(), y.into(), layer.into()]
+}
+
+fn dot(a: Vector, b: Vector) -> i128 {
+    a.iter().zip(&b).map(|(a, b)| a * b).sum()
+}
+
+fn cross(a: Vector, b: Vector) -> Vector {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+/// The vector divided by the largest factor common to its components.
+fn reduce(v: Vector) -> Vector {
+    fn gcd(a: i128, b: i128) -> i128 {
+        if b == 0 {
+            a
+        } else {
+            gcd(b, a % b)
+        }
+    }
+    let factor = v.iter().fold(0, |g, &c| gcd(g, c.abs())).max(1);
+    v.map(|c| c / factor)
+}
+
+/// The cells one wire visits, as merged intervals along each line.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Cover {
+    lines: HashMap<Line, Vec<Interval>>,
+}
+
+/// Sort the intervals and join those that overlap or touch.
+fn merge(intervals: &mut Vec<Interval>) {
+    intervals.sort_unstable();
+    let mut merged: Vec<Interval> = Vec::with_capacity(intervals.len());
+    for &(start, end) in intervals.iter() {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 + 1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    *intervals = merged;
+}
+
+fn contains(intervals: &[Interval], value: i128) -> bool {
+    // The first interval ending at or after the value is the only one that can hold it.
+    let index = intervals.partition_point(|&(_, end)| end < value);
+    intervals
+        .get(index)
+        .is_some_and(|&(start, _)| start <= value)
+}
+
+/// Values in both interval lists, which must each be merged.
+fn common(a: &[Interval], b: &[Interval]) -> Vec<i128> {
+    let mut values = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        let start = a[i].0.max(b[j].0);
+        let end = a[i].1.min(b[j].1);
+        values.extend(start..=end);
+        if a[i].1 < b[j].1 {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    values
+}
+
+impl Cover {
+    /// Like `get_positions`, the cells after each span's start, so the origin is only
+    /// covered if the wire comes back to it.
+    pub fn new(wire: &[Span]) -> Cover {
+        let mut cover = Cover::default();
+        for segment in segments(wire) {
+            let intervals = cover.lines.entry(segment.line).or_default();
+            intervals.push(segment.range());
+        }
+        cover.lines.values_mut().for_each(merge);
+        cover
+    }
+
+    pub fn contains(&self, position: Position) -> bool {
+        AXES.iter().any(|&axis| {
+            let (line, t) = Line::through(position, axis);
+            self.lines
+                .get(&line)
+                .is_some_and(|intervals| contains(intervals, t))
+        })
+    }
+
+    /// Number of intervals stored, at most one per span.
+    pub fn intervals(&self) -> usize {
+        self.lines.values().map(Vec::len).sum()
+    }
+
+    fn cells(&self) -> impl Iterator<Item = Position> + '_ {
+        self.lines.iter().flat_map(|(line, intervals)| {
+            intervals
+                .iter()
+                .flat_map(move |&(start, end)| (start..=end).filter_map(move |t| line.at(t)))
+        })
+    }
+
+    fn along(&self, axis: Position) -> impl Iterator<Item = (&Line, &Vec<Interval>)> {
+        self.lines.iter().filter(move |(line, _)| line.axis == axis)
+    }
+}
+
+/// Cells where lines of `a` along one axis cross lines of `b` along another.
+fn across(a: &Cover, axis_a: Position, b: &Cover, axis_b: Position) -> HashSet<Position> {
+    // Lines along `a` open before lines along `b` at the same sweep coordinate are checked,
+    // and close after.
+    #[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+    enum Event {
+        Open(usize),
+        Check(usize),
+        Close(usize),
+    }
+
+    let (da, db) = (vector(axis_a), vector(axis_b));
+    let normal = cross(da, db);
+    let (plane, row, sweep) = (
+        reduce(normal),
+        reduce(cross(normal, da)),
+        reduce(cross(normal, db)),
+    );
+
+    let mut events = vec![];
+    // Plane and row of each interval along `a`, by where the sweep meets it.
+    let mut rows = vec![];
+    for (line, intervals) in a.along(axis_a) {
+        let key = (dot(plane, line.origin), dot(row, line.origin));
+        for &(start, end) in intervals {
+            let at = |t: i128| dot(sweep, line.origin) + t * dot(sweep, da);
+            events.push((at(start).min(at(end)), Event::Open(rows.len())));
+            events.push((at(start).max(at(end)), Event::Close(rows.len())));
+            rows.push((key, line));
+        }
+    }
+    // Plane and the range of rows of each interval along `b`.
+    let mut checks = vec![];
+    for (line, intervals) in b.along(axis_b) {
+        let plane = dot(plane, line.origin);
+        for &(start, end) in intervals {
+            let at = |t: i128| dot(row, line.origin) + t * dot(row, db);
+            let range = (
+                (plane, at(start).min(at(end))),
+                (plane, at(start).max(at(end))),
+            );
+            events.push((dot(sweep, line.origin), Event::Check(checks.len())));
+            checks.push((range, line, (start, end)));
+        }
+    }
+    events.sort_unstable();
+
+    // Merged intervals never overlap along a line, and each row has one line, so at most one
+    // interval is open in each.
+    let mut open = BTreeMap::new();
+    let mut crossings = HashSet::new();
+    for (at, event) in events {
+        match event {
+            Event::Open(index) => {
+                open.insert(rows[index].0, index);
+            }
+            Event::Check(index) => {
+                let ((lo, hi), line_b, interval) = checks[index];
+                for (_, &row_index) in open.range(lo..=hi) {
+                    let line_a: &Line = rows[row_index].1;
+                    // How far along `a` the sweep is, if it's at a cell.
+                    let offset = at - dot(sweep, line_a.origin);
+                    let step = dot(sweep, da);
+                    if offset % step != 0 {
+                        continue;
+                    }
+                    let cell = match line_a.at(offset / step) {
+                        Some(cell) => cell,
+                        None => continue,
+                    };
+                    let (line, t) = Line::through(cell, axis_b);
+                    if line == *line_b && interval.0 <= t && t <= interval.1 {
+                        crossings.insert(cell);
+                    }
+                }
+            }
+            Event::Close(index) => {
+                open.remove(&rows[index].0);
+            }
+        }
+    }
+    crossings
+}
+
+/// Cells where the two wires run along the same line.
+fn collinear(a: &Cover, b: &Cover) -> HashSet<Position> {
+    let mut overlaps = HashSet::new();
+    for (line, intervals) in &a.lines {
+        if let Some(other) = b.lines.get(line) {
+            overlaps.extend(
+                common(intervals, other)
+                    .into_iter()
+                    .filter_map(|t| line.at(t)),
+            );
+        }
+    }
+    overlaps
+}
+
+/// Cells visited by both wires.
+pub fn crossings(a: &Cover, b: &Cover) -> HashSet<Position> {
+    let mut crossings = collinear(a, b);
+    for &axis_a in &AXES {
+        for &axis_b in AXES.iter().filter(|&&axis| axis != axis_a) {
+            crossings.extend(across(a, axis_a, b, axis_b));
+        }
+    }
+    crossings
+}
+
+/// Points visited by every wire, the same as `crate::all_intersections`.
+pub fn all_intersections(wires: &[Vec<Span>]) -> HashSet<Position> {
+    let covers: Vec<Cover> = wires.iter().map(|wire| Cover::new(wire)).collect();
+    let mut common_values = match &covers[..] {
+        [] => HashSet::new(),
+        // Nothing to cross, so every cell of the wire.
+        [only] => only.cells().collect(),
+        [first, second, ..] => crossings(first, second),
+    };
+    for cover in covers.iter().skip(2) {
+        common_values.retain(|&position| cover.contains(position));
+    }
+    common_values
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parse_wires, Moves};
+    use common::Res;
+
+    fn check(input: &str) -> Res<HashSet<Position>> {
+        check_moves(input, Moves::FOUR_WAY)
+    }
+
+    fn check_moves(input: &str, moves: Moves) -> Res<HashSet<Position>> {
+        let wires = moves.parse_wires(input)?;
+        let expected = crate::all_intersections(&wires);
+        assert_eq!(all_intersections(&wires), expected, "{}", input);
+        Ok(expected)
+    }
+
+    #[test]
+    fn matches_raster() -> Res<()> {
+        let expected: HashSet<Position> = vec![(3, -3, 0), (6, -5, 0)].into_iter().collect();
+        assert_eq!(check("R8,U5,L5,D3\nU7,R6,D4,L4")?, expected);
+        check("R75,D30,R83,U83,L12,D49,R71,U7,L72\nU62,R66,U55,R34,D71,R55,D58,R83")?;
+        check("R98,U47,R26,D63,R33,U87,L62,D20,R33,U53,R51\nU98,R91,D20,R16,D67,R40,U7,R15,U6,R7")?;
+        // Shared runs, touching ends, a return to the origin and a third wire.
+        assert_eq!(check("R10\nR3,U1,R2,D1,R2")?.len(), 6);
+        check("R5,L10\nL3,R1")?;
+        check("R2,U2,L2,D2\nU1,R5")?;
+        check("R2,U2,L2,D2\nU1,R5\nU3,R1,D2")?;
+        check("R0,U3\nD0,L1,U2,R4")?;
+        Ok(())
+    }
+
+    #[test]
+    fn diagonals_and_layers() -> Res<()> {
+        // Diagonals crossing at a cell, and between cells.
+        let crossing = vec![(2, 2, 0)].into_iter().collect();
+        assert_eq!(check_moves("DR4\nR4,DL4", Moves::EIGHT_WAY)?, crossing);
+        assert!(check_moves("DR2\nR1,DL1", Moves::EIGHT_WAY)?.is_empty());
+        // Along the same diagonal, and across rows and columns.
+        assert_eq!(check_moves("DR5\nR2,D2,DR4", Moves::EIGHT_WAY)?.len(), 4);
+        assert_eq!(check_moves("UR4,L4\nU5,R1,D5", Moves::EIGHT_WAY)?.len(), 3);
+        let layers = vec![(3, 0, 1), (3, 1, 1), (3, 2, 1)].into_iter().collect();
+        assert_eq!(
+            check_moves("R3,A1,D3\nA1,R3,D2,B1,D1", Moves::LAYERED)?,
+            layers
+        );
+        // Columns between layers crossing everything else.
+        check_moves("R2,B2,UL2\nB1,R2,A4,DR1", Moves::ALL)?;
+        check_moves("UL3,A3,DR3,B6\nA2,UL2,B5,R1,A2", Moves::ALL)?;
+        Ok(())
+    }
+
+    fn random_wires(moves: Moves, seed: u32, max_dist: u32) -> Res<()> {
+        let mut state = seed;
+        let mut next = move |n: u32| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) % n
+        };
+        let directions = moves.directions();
+        for _ in 0..50 {
+            let mut wires = vec![];
+            for _ in 0..2 + next(2) {
+                let spans: Vec<String> = (0..20)
+                    .map(|_| {
+                        let direction = directions[next(directions.len() as u32) as usize];
+                        format!("{}{}", direction, next(max_dist))
+                    })
+                    .collect();
+                wires.push(spans.join(","));
+            }
+            check_moves(&wires.join("\n"), moves)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn matches_raster_on_random_wires() -> Res<()> {
+        random_wires(Moves::FOUR_WAY, 12345, 30)?;
+        random_wires(Moves::EIGHT_WAY, 999, 15)?;
+        random_wires(Moves::LAYERED, 4242, 10)?;
+        // Short spans, so diagonals and layers keep crossing each other.
+        random_wires(Moves::ALL, 7, 6)?;
+        Ok(())
+    }
+
+    #[test]
+    fn long_wires() -> Res<()> {
+        // Millions of steps, but only a handful of spans.
+        let wires = parse_wires("R3000000,U10,L3000000\nU20,R1500000,D30,R2000000")?;
+        let covers: Vec<Cover> = wires.iter().map(|wire| Cover::new(wire)).collect();
+        assert_eq!(covers[0].intervals() + covers[1].intervals(), 7);
+        let expected: HashSet<Position> = vec![(0, -10, 0), (1_500_000, 0, 0), (1_500_000, -10, 0)]
+            .into_iter()
+            .collect();
+        assert_eq!(all_intersections(&wires), expected);
+        assert_eq!(all_intersections(&wires), crate::all_intersections(&wires));
+        Ok(())
+    }
+}
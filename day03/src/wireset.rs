@@ -0,0 +1,291 @@
+use crate::{get_positions, Coord, Position, Span};
+use std::collections::BTreeMap;
+use std::ops::Bound::{Excluded, Unbounded};
+
+/*
+ * Loaded wires, indexed for queries.
+ *
+ * Every cell the wires visit is stored once, by layer, then column, then row, with the wires
+ * that visit it and the step each first gets there. As everywhere else, a wire's cells don't
+ * include the origin it starts from unless it comes back to it.
+ */
+
+/// A wire reaching a cell.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Visit {
+    pub wire: usize,
+    /// Steps along the wire to its first visit.
+    pub step: usize,
+}
+
+/// Visits to the cells of one layer, by x then y.
+type Columns = BTreeMap<Coord, BTreeMap<Coord, Vec<Visit>>>;
+
+#[derive(Clone, Debug, Default)]
+pub struct WireSet {
+    wires: Vec<Vec<Span>>,
+    /// Visits to each cell, by layer, then x, then y, in wire order.
+    layers: BTreeMap<Coord, Columns>,
+    /// Corners of each wire's bounding box, including the origin.
+    bounds: Vec<(Position, Position)>,
+}
+
+impl WireSet {
+    pub fn new(wires: Vec<Vec<Span>>) -> WireSet {
+        let mut layers: BTreeMap<Coord, Columns> = BTreeMap::new();
+        let mut bounds = vec![];
+        for (wire, spans) in wires.iter().enumerate() {
+            let (mut min, mut max): (Position, Position) = ((0, 0, 0), (0, 0, 0));
+            for (step, (x, y, layer)) in get_positions(spans).into_iter().enumerate() {
+                min = (min.0.min(x), min.1.min(y), min.2.min(layer));
+                max = (max.0.max(x), max.1.max(y), max.2.max(layer));
+                let visits = layers
+                    .entry(layer)
+                    .or_default()
+                    .entry(x)
+                    .or_default()
+                    .entry(y)
+                    .or_default();
+                if visits.last().is_none_or(|visit| visit.wire != wire) {
+                    visits.push(Visit {
+                        wire,
+                        step: step + 1,
+                    });
+                }
+            }
+            bounds.push((min, max));
+        }
+        WireSet {
+            wires,
+            layers,
+            bounds,
+        }
+    }
+
+    pub fn wires(&self) -> &[Vec<Span>] {
+        &self.wires
+    }
+
+    pub fn len(&self) -> usize {
+        self.wires.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.wires.is_empty()
+    }
+
+    /// Every wire through the cell and the step it first gets there, in wire order.
+    pub fn visits(&self, (x, y, layer): Position) -> &[Visit] {
+        self.layers
+            .get(&layer)
+            .and_then(|columns| columns.get(&x))
+            .and_then(|column| column.get(&y))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Indices of the wires through the cell.
+    pub fn wires_at(&self, position: Position) -> Vec<usize> {
+        self.visits(position)
+            .iter()
+            .map(|visit| visit.wire)
+            .collect()
+    }
+
+    /// Steps the wire takes to first reach the cell, if it ever does.
+    pub fn first_step(&self, wire: usize, position: Position) -> Option<usize> {
+        self.visits(position)
+            .iter()
+            .find(|visit| visit.wire == wire)
+            .map(|visit| visit.step)
+    }
+
+    /// Lowest and highest corners of the box around the wire and the origin.
+    pub fn bounding_box(&self, wire: usize) -> Option<(Position, Position)> {
+        self.bounds.get(wire).cloned()
+    }
+
+    /// The wire cell closest to the position by Manhattan distance, and the lowest-numbered
+    /// wire through it. Ties go to the smallest position.
+    pub fn nearest(&self, (x, y, layer): Position) -> Option<(usize, Position)> {
+        let mut best: Option<(u64, Position)> = None;
+        // Layers nearest first. There are few, so sorting them all is cheap.
+        let mut layers: Vec<_> = self
+            .layers
+            .iter()
+            .map(|(&l, columns)| (layer.abs_diff(l), l, columns))
+            .collect();
+        layers.sort_unstable_by_key(|&(dist, l, _)| (dist, l));
+        for (layer_dist, l, columns) in layers {
+            if best.is_some_and(|(best, _)| layer_dist > best) {
+                break;
+            }
+            nearest_in(columns, (x, y), (layer_dist, l), &mut best);
+        }
+        best.map(|(_, position)| (self.visits(position)[0].wire, position))
+    }
+
+    /// Every wire cell with `min.0 <= x <= max.0`, `min.1 <= y <= max.1` and
+    /// `min.2 <= layer <= max.2`, and which wires visit it, ordered by layer, x, then y.
+    pub fn cells_in(&self, min: Position, max: Position) -> Vec<(Position, Vec<usize>)> {
+        if min.0 > max.0 || min.1 > max.1 || min.2 > max.2 {
+            return vec![];
+        }
+        self.layers
+            .range(min.2..=max.2)
+            .flat_map(|(&layer, columns)| {
+                columns.range(min.0..=max.0).flat_map(move |(&x, rows)| {
+                    rows.range(min.1..=max.1).map(move |(&y, visits)| {
+                        (
+                            (x, y, layer),
+                            visits.iter().map(|visit| visit.wire).collect(),
+                        )
+                    })
+                })
+            })
+            .collect()
+    }
+}
+
+/// Updates `best` with the closest cell of one layer, `layer_dist` layers away.
+fn nearest_in(
+    columns: &Columns,
+    (x, y): (Coord, Coord),
+    (layer_dist, layer): (u64, Coord),
+    best: &mut Option<(u64, Position)>,
+) {
+    // Distances are unsigned, so they can't overflow between any two coordinates.
+    let dist = |cx: Coord, cy: Coord| {
+        layer_dist
+            .saturating_add(x.abs_diff(cx))
+            .saturating_add(y.abs_diff(cy))
+    };
+    // Columns outwards from x on both sides, nearest first.
+    let mut left = columns.range(..=x).rev().peekable();
+    let mut right = columns.range((Excluded(x), Unbounded)).peekable();
+    loop {
+        let column = match (left.peek(), right.peek()) {
+            (Some(&(&l, _)), Some(&(&r, _))) if x.abs_diff(l) <= x.abs_diff(r) => left.next(),
+            (Some(_), None) => left.next(),
+            (_, Some(_)) => right.next(),
+            (None, None) => None,
+        };
+        let (&cx, rows) = match column {
+            Some(column) => column,
+            None => break,
+        };
+        if best.is_some_and(|(best, _)| layer_dist.saturating_add(x.abs_diff(cx)) > best) {
+            break;
+        }
+        let above = rows.range(..=y).next_back();
+        let below = rows.range(y..).next();
+        for (&cy, _) in above.into_iter().chain(below) {
+            let candidate = (dist(cx, cy), (cx, cy, layer));
+            if best.is_none_or(|b| candidate < b) {
+                *best = Some(candidate);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parse_wires, Moves};
+    use common::Res;
+
+    fn example() -> Res<WireSet> {
+        Ok(WireSet::new(parse_wires("R8,U5,L5,D3\nU7,R6,D4,L4")?))
+    }
+
+    #[test]
+    fn points() -> Res<()> {
+        let set = example()?;
+        assert_eq!(set.len(), 2);
+        assert_eq!(set.wires_at((3, -3, 0)), vec![0, 1]);
+        assert_eq!(set.wires_at((8, 0, 0)), vec![0]);
+        assert!(set.wires_at((0, 0, 0)).is_empty());
+        assert!(set.wires_at((3, -3, 1)).is_empty());
+        assert_eq!(set.first_step(0, (6, -5, 0)), Some(15));
+        assert_eq!(set.first_step(1, (6, -5, 0)), Some(15));
+        assert_eq!(set.first_step(1, (8, 0, 0)), None);
+        assert_eq!(
+            set.visits((3, -3, 0)),
+            &[Visit { wire: 0, step: 20 }, Visit { wire: 1, step: 20 }]
+        );
+        // Coming back over a cell keeps the first visit.
+        let set = WireSet::new(parse_wires("R3,L2")?);
+        assert_eq!(set.visits((2, 0, 0)), &[Visit { wire: 0, step: 2 }]);
+        let set = WireSet::new(Moves::ALL.parse_wires("A2,DR1\nDR1,A2")?);
+        assert_eq!(set.wires_at((1, 1, 2)), vec![0, 1]);
+        assert_eq!(set.first_step(1, (1, 1, 2)), Some(3));
+        Ok(())
+    }
+
+    #[test]
+    fn boxes() -> Res<()> {
+        let set = example()?;
+        assert_eq!(set.bounding_box(0), Some(((0, -5, 0), (8, 0, 0))));
+        assert_eq!(set.bounding_box(1), Some(((0, -7, 0), (6, 0, 0))));
+        assert_eq!(set.bounding_box(2), None);
+        let set = WireSet::new(Moves::ALL.parse_wires("B2,UL3")?);
+        assert_eq!(set.bounding_box(0), Some(((-3, -3, -2), (0, 0, 0))));
+        Ok(())
+    }
+
+    /// Checks `nearest` against every cell, from every position in the box.
+    fn check_nearest(set: &WireSet, min: Position, max: Position) {
+        let far = (Coord::MIN, Coord::MIN, Coord::MIN);
+        let cells = set.cells_in(far, (Coord::MAX, Coord::MAX, Coord::MAX));
+        for layer in min.2..=max.2 {
+            for y in min.1..=max.1 {
+                for x in min.0..=max.0 {
+                    let expected = cells
+                        .iter()
+                        .map(|&(p, _)| ((p.0 - x).abs() + (p.1 - y).abs() + (p.2 - layer).abs(), p))
+                        .min()
+                        .map(|(_, p)| p);
+                    assert_eq!(set.nearest((x, y, layer)).map(|(_, p)| p), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nearest() -> Res<()> {
+        let set = example()?;
+        assert_eq!(set.nearest((3, -3, 0)), Some((0, (3, -3, 0))));
+        assert_eq!(set.nearest((10, 2, 0)), Some((0, (8, 0, 0))));
+        assert_eq!(set.nearest((-4, -6, 0)), Some((1, (0, -6, 0))));
+        // Walled in, with wire cells one step away on three sides.
+        assert_eq!(set.nearest((4, -4, 0)), Some((0, (3, -4, 0))));
+        assert_eq!(set.nearest((4, -4, 3)), Some((0, (3, -4, 0))));
+        assert_eq!(WireSet::new(vec![]).nearest((0, 0, 0)), None);
+        // Far enough away that differences don't fit in a coordinate.
+        assert_eq!(
+            set.nearest((Coord::MIN, Coord::MAX, Coord::MAX)),
+            Some((1, (0, -1, 0)))
+        );
+        check_nearest(&set, (-3, -9, -1), (10, 2, 1));
+        let set = WireSet::new(Moves::ALL.parse_wires("R4,A3,UL4\nB2,DR3,A4,L5")?);
+        check_nearest(&set, (-3, -6, -4), (6, 5, 5));
+        Ok(())
+    }
+
+    #[test]
+    fn rectangles() -> Res<()> {
+        let set = example()?;
+        assert_eq!(
+            set.cells_in((2, -4, 0), (3, -3, 0)),
+            vec![
+                ((2, -3, 0), vec![1]),
+                ((3, -4, 0), vec![0]),
+                ((3, -3, 0), vec![0, 1]),
+            ]
+        );
+        assert!(set.cells_in((4, -4, 0), (5, -4, 0)).is_empty());
+        assert!(set.cells_in((3, -3, 0), (2, -3, 0)).is_empty());
+        assert!(set.cells_in((2, -4, 1), (3, -3, 1)).is_empty());
+        assert_eq!(set.cells_in((-100, -100, 0), (100, 100, 0)).len(), 40);
+        Ok(())
+    }
+}
@@ -1,8 +1,23 @@
 use clap::{App, Arg};
 use common::{error, Res};
+use intcode::arcade::{free_play, Arcade, Joystick, Keyboard, TrackBall};
+use intcode::asm::assemble;
+use intcode::coverage::Coverage;
+use intcode::device::run_device;
+use intcode::disasm::disassemble;
+use intcode::droid::{Maze, Strategy};
+use intcode::hostcall::{self, Sandbox};
+use intcode::limits::Limits;
+use intcode::loader::{load, Program};
+use intcode::optimize::optimize;
+use intcode::paint::{Colour, PaintRobot};
+use intcode::patch::{parse_patch, patch};
+use intcode::probe::Probe;
+use intcode::session::Session;
+use intcode::{Dialect, IntCode, Machine, Registry};
 use std::fs;
-
-type IntCode = i32;
+use std::io::{self, BufRead};
+use std::sync::{Arc, Mutex};
 
 /*
  * Validate args, parse input, and run program.
@@ -17,22 +32,320 @@ fn main() -> Res<()> {
         )
         .arg(
             Arg::with_name("inputs")
-                .help("Comma-separated list of inputs.")
-                .required(true),
+                .help("Comma-separated list of inputs. With --coverage, separate runs with ';'.")
+                .required_unless_one(&[
+                    "replay",
+                    "disassemble",
+                    "paint",
+                    "arcade",
+                    "maze",
+                    "probe",
+                ]),
+        )
+        .arg(
+            Arg::with_name("optimize")
+                .short("O")
+                .long("optimize")
+                .help("Run through the peephole optimiser."),
+        )
+        .arg(
+            Arg::with_name("coverage")
+                .long("coverage")
+                .help("Print an annotated listing with per-instruction hit counts."),
+        )
+        .arg(
+            Arg::with_name("lcov")
+                .long("lcov")
+                .value_name("FILE")
+                .help("Write coverage in LCOV format to the given file."),
+        )
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .value_name("FILE")
+                .help("Record I/O to a session file, prompting on stdin once inputs run out."),
+        )
+        .arg(
+            Arg::with_name("replay")
+                .long("replay")
+                .value_name("FILE")
+                .conflicts_with("record")
+                .help("Replay a recorded session and report the first divergence."),
+        )
+        .arg(
+            Arg::with_name("dialect")
+                .long("dialect")
+                .value_name("DIALECT")
+                .possible_values(&["day02", "day05", "full"])
+                .help("Instruction set to run with; defaults to the program's, or day05."),
+        )
+        .arg(
+            Arg::with_name("allow")
+                .long("allow")
+                .value_name("CALLS")
+                .possible_values(&["random", "steps", "log"])
+                .use_delimiter(true)
+                .help("Enable the sys instruction and allow these host calls."),
+        )
+        .arg(
+            Arg::with_name("allow-file")
+                .long("allow-file")
+                .value_name("FILE")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Enable the sys instruction and allow reading the file, numbered from 0."),
+        )
+        .arg(
+            Arg::with_name("max-memory")
+                .long("max-memory")
+                .value_name("CELLS")
+                .help("Fault if memory would grow beyond this many cells."),
+        )
+        .arg(
+            Arg::with_name("max-outputs")
+                .long("max-outputs")
+                .value_name("COUNT")
+                .help("Fault once the program outputs more than this many values."),
+        )
+        .arg(
+            Arg::with_name("protect-code")
+                .long("protect-code")
+                .help("Fault if the program writes to its own code."),
+        )
+        .arg(
+            Arg::with_name("paint")
+                .long("paint")
+                .value_name("START")
+                .possible_values(&["black", "white"])
+                .help("Drive a hull-painting robot starting on a panel of this colour."),
+        )
+        .arg(
+            Arg::with_name("ppm")
+                .long("ppm")
+                .value_name("FILE")
+                .requires("paint")
+                .help("Write the painted hull to a PPM image."),
+        )
+        .arg(
+            Arg::with_name("arcade")
+                .long("arcade")
+                .value_name("PLAYER")
+                .possible_values(&["auto", "keyboard"])
+                .help("Play an arcade game, tracking the ball or reading a/d moves from stdin."),
+        )
+        .arg(
+            Arg::with_name("free-play")
+                .long("free-play")
+                .requires("arcade")
+                .help("Patch the game so it needs no quarters."),
+        )
+        .arg(
+            Arg::with_name("maze")
+                .long("maze")
+                .value_name("STRATEGY")
+                .possible_values(&["snapshots", "backtracking"])
+                .help("Map the maze of a repair droid program and find its target."),
+        )
+        .arg(
+            Arg::with_name("probe")
+                .long("probe")
+                .value_name("SIZE")
+                .help("Count points a beam program reports as affected in a SIZE by SIZE region."),
+        )
+        .arg(
+            Arg::with_name("square")
+                .long("square")
+                .value_name("SIZE")
+                .requires("probe")
+                .help("Also find the nearest SIZE by SIZE square that fits in the beam."),
+        )
+        .arg(
+            Arg::with_name("patch")
+                .long("patch")
+                .value_name("ADDR=VALUE")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Overwrite a cell of the program before running it."),
+        )
+        .arg(
+            Arg::with_name("disassemble")
+                .long("disassemble")
+                .help("Print the program as assembly instead of running it."),
         )
         .get_matches();
 
     let filename = args.value_of("INPUT").unwrap();
     let inputs = args.value_of("inputs").unwrap_or("");
 
-    // Load and parse list of numbers.
-    let contents = fs::read_to_string(filename)?;
+    // Assembly sources carry no metadata, so they need the dialect up front.
+    let dialect_arg = args
+        .value_of("dialect")
+        .map(str::parse::<Dialect>)
+        .transpose()?;
+    let mut program = if filename.ends_with(".asm") {
+        let registry = Registry::new(dialect_arg.unwrap_or_default());
+        Program::new(assemble(&fs::read_to_string(filename)?, &registry)?)
+    } else {
+        load(filename)?
+    };
+    let dialect = dialect_arg.unwrap_or_else(|| program.metadata.dialect.unwrap_or_default());
+    let patches = args
+        .values_of("patch")
+        .into_iter()
+        .flatten()
+        .map(parse_patch)
+        .collect::<Res<Vec<_>>>()?;
+    program.code = patch(&program.code, &patches)?;
+    program.metadata.dialect = Some(dialect);
+    let intcodes = &program.code;
+    println!("Loaded {} intcodes ({}).", intcodes.len(), dialect);
+
+    if args.is_present("disassemble") {
+        print!("{}", disassemble(intcodes, &Registry::new(dialect)));
+        return Ok(());
+    }
+
+    if args.is_present("coverage") || args.is_present("lcov") {
+        let runs = inputs
+            .split(';')
+            .map(parse)
+            .collect::<Res<Vec<Vec<IntCode>>>>()?;
+        let coverage = run_with_coverage(&program, runs)?;
+        if args.is_present("coverage") {
+            print!("{}", coverage.listing(intcodes));
+        }
+        if let Some(lcov_filename) = args.value_of("lcov") {
+            fs::write(lcov_filename, coverage.lcov(intcodes, filename))?;
+        }
+        return Ok(());
+    }
+
+    if let Some(start) = args.value_of("paint") {
+        let start = if start == "white" {
+            Colour::White
+        } else {
+            Colour::Black
+        };
+        let robot = paint(&program, start)?;
+        println!("Painted {} panels.", robot.painted());
+        print!("{}", robot.render());
+        if let Some(ppm_filename) = args.value_of("ppm") {
+            fs::write(ppm_filename, robot.ppm(4))?;
+        }
+        return Ok(());
+    }
+
+    if let Some(player) = args.value_of("arcade") {
+        if args.is_present("free-play") {
+            program.code = free_play(&program.code)?;
+        }
+        let arcade = if player == "keyboard" {
+            let keyboard = Keyboard::new(io::stdin().lock());
+            play(&program, Box::new(keyboard), true)?
+        } else {
+            play(&program, Box::new(TrackBall), false)?
+        };
+        print!("{}", arcade.screen().render());
+        println!("Frames: {}", arcade.frames());
+        return Ok(());
+    }
+
+    if let Some(strategy) = args.value_of("maze") {
+        let strategy = if strategy == "backtracking" {
+            Strategy::Backtracking
+        } else {
+            Strategy::Snapshots
+        };
+        let maze = Maze::explore(&program.machine(), strategy)?;
+        print!("{}", maze.render());
+        match maze.target() {
+            Some(target) => {
+                println!("Target at {:?}.", target);
+                if let Some(moves) = maze.shortest_path((0, 0), target) {
+                    println!("Shortest path: {} moves.", moves);
+                }
+                if let Some(moves) = maze.flood_fill(target) {
+                    println!("Flood fill from target: {} moves.", moves);
+                }
+            }
+            None => println!("No target found."),
+        }
+        return Ok(());
+    }
+
+    if let Some(size) = args.value_of("probe") {
+        let size: IntCode = size.parse()?;
+        let mut probe = Probe::new(program.machine());
+        println!("Affected: {}", probe.count(0..size, 0..size)?);
+        if let Some(square) = args.value_of("square") {
+            match probe.find_square(square.parse()?, 10_000, 10_000)? {
+                Some((x, y)) => println!("Square at ({}, {}): {}", x, y, x * 10_000 + y),
+                None => println!("No square found."),
+            }
+        }
+        println!("Queries: {}", probe.queries());
+        return Ok(());
+    }
+
+    if let Some(session_filename) = args.value_of("replay") {
+        let session = Session::parse(&fs::read_to_string(session_filename)?)?;
+        let mut machine = program.machine();
+        match session.replay(&mut machine)? {
+            Some(mismatch) => return error(format!("Replay diverged at {}", mismatch)),
+            None => println!("Replayed {} events.", session.events.len()),
+        }
+        return Ok(());
+    }
 
-    let intcodes: Vec<IntCode> = parse(contents)?;
-    println!("Loaded {} intcodes.", intcodes.len());
     let inputs: Vec<IntCode> = parse(inputs)?;
-    let mut intcodes = intcodes.clone();
-    println!("outputs: {:?}", run(&mut intcodes, inputs));
+    if let Some(session_filename) = args.value_of("record") {
+        let session = record(&program, inputs)?;
+        fs::write(session_filename, session.to_string())?;
+        println!("Recorded {} events.", session.events.len());
+        return Ok(());
+    }
+
+    let sandboxed = [
+        "allow",
+        "allow-file",
+        "max-memory",
+        "max-outputs",
+        "protect-code",
+    ]
+    .iter()
+    .any(|&arg| args.is_present(arg));
+    if sandboxed {
+        let mut sandbox = Sandbox::new();
+        for call in args.values_of("allow").into_iter().flatten() {
+            sandbox = match call {
+                "random" => sandbox.allow_random(0x2019_0005),
+                "steps" => sandbox.allow_steps(),
+                _ => sandbox.allow_log(),
+            };
+        }
+        for file in args.values_of("allow-file").into_iter().flatten() {
+            sandbox = sandbox.allow_file(file);
+        }
+        let mut limits = Limits::default();
+        if let Some(cells) = args.value_of("max-memory") {
+            limits = limits.with_max_memory(cells.parse()?);
+        }
+        if let Some(count) = args.value_of("max-outputs") {
+            limits = limits.with_max_outputs(count.parse()?);
+        }
+        if args.is_present("protect-code") {
+            limits = limits.with_read_only(0..program.code.len());
+        }
+        let (outputs, logs) = run_sandboxed(&program, inputs, sandbox, limits)?;
+        for message in logs {
+            println!("log: {}", message);
+        }
+        println!("outputs: {:?}", outputs);
+    } else if args.is_present("optimize") {
+        println!("outputs: {:?}", run_optimized(&mut program, inputs));
+    } else {
+        println!("outputs: {:?}", run(&mut program, inputs));
+    }
 
     Ok(())
 }
@@ -40,7 +353,7 @@ fn main() -> Res<()> {
 fn parse<S: Into<String>>(contents: S) -> Res<Vec<IntCode>> {
     let contents = contents.into();
     let contents = contents.trim();
-    if contents.len() == 0 {
+    if contents.is_empty() {
         return Ok(vec![]);
     }
     let mut intcodes: Vec<IntCode> = vec![];
@@ -57,137 +370,99 @@ fn parse<S: Into<String>>(contents: S) -> Res<Vec<IntCode>> {
     Ok(intcodes)
 }
 
-#[derive(Clone, Copy, Debug)]
-enum ParameterMode {
-    Position,
-    Immediate,
+/// A machine for the program, starting at its entry point with the inputs queued.
+fn new_machine(program: &Program, inputs: Vec<IntCode>) -> Machine {
+    let mut machine = program.machine();
+    for input in inputs {
+        machine.push_input(input);
+    }
+    machine
 }
 
-impl ParameterMode {
-    fn from_int(value: i32) -> Res<ParameterMode> {
-        match value {
-            0 => Ok(ParameterMode::Position),
-            1 => Ok(ParameterMode::Immediate),
-            _ => error("Unsupported parameter mode."),
-        }
-    }
+/// Run the given IntCode program, leaving the final memory state in its code.
+fn run(program: &mut Program, inputs: Vec<IntCode>) -> Res<Vec<IntCode>> {
+    let mut machine = new_machine(program, inputs);
+    machine.run()?;
+    let outputs = machine.take_outputs();
+    program.code = machine.into_memory();
+    Ok(outputs)
 }
 
-/// Run the given IntCode program.
-fn run(mut intcodes: &mut Vec<IntCode>, inputs: Vec<IntCode>) -> Res<Vec<IntCode>> {
-    let mut pc = 0;
-
-    let mut inputs = inputs.clone();
-    let mut outputs = vec![];
-
-    while pc < intcodes.len() {
-        let instruction = intcodes[pc];
-        let op_code = instruction % 100;
-        let parameter_mode0 = ParameterMode::from_int((instruction / 100) % 10)?;
-        let parameter_mode1 = ParameterMode::from_int((instruction / 1000) % 10)?;
-        // Unused: let parameter_mode2 = ParameterMode::from_int((instruction / 10000) % 10);
-        let modes = (parameter_mode0, parameter_mode1);
-        match op_code {
-            // Add
-            1 => {
-                let (src0, src1, dst_addr) = get_two_operands_and_dst(&mut intcodes, pc, modes)?;
-                intcodes[dst_addr] = src0 + src1;
-                pc += 4;
-            }
-            // Multiply
-            2 => {
-                let (src0, src1, dst_addr) = get_two_operands_and_dst(&mut intcodes, pc, modes)?;
-                intcodes[dst_addr] = src0 * src1;
-                pc += 4;
-            }
-            // Input
-            3 => {
-                let next_item: Vec<_> = inputs.drain(0..1).collect();
-                let inp = next_item[0];
-                println!("input {}", inp);
-                let dst_addr = intcodes[pc + 1] as usize;
-                intcodes[dst_addr] = inp;
-                pc += 2;
-            }
-            // Output
-            4 => {
-                let src_addr = intcodes[pc + 1] as usize;
-                outputs.push(intcodes[src_addr]);
-                pc += 2;
-            }
-            // Jump if True
-            5 => {
-                let condition = get_op(intcodes, pc + 1, parameter_mode0)?;
-                let new_pc = get_op(intcodes, pc + 2, parameter_mode1)?;
-                if condition != 0 {
-                    pc = new_pc as usize;
-                } else {
-                    pc += 3;
-                }
-            }
-            // Jump if False
-            6 => {
-                let condition = get_op(intcodes, pc + 1, parameter_mode0)?;
-                let new_pc = get_op(intcodes, pc + 2, parameter_mode1)?;
-                if condition == 0 {
-                    pc = new_pc as usize;
-                } else {
-                    pc += 3;
-                }
-            }
-            // Less than
-            7 => {
-                let (src0, src1, dst_addr) = get_two_operands_and_dst(&mut intcodes, pc, modes)?;
-                intcodes[dst_addr] = if src0 < src1 { 1 } else { 0 };
-                pc += 4;
-            }
-            // Equals
-            8 => {
-                let (src0, src1, dst_addr) = get_two_operands_and_dst(&mut intcodes, pc, modes)?;
-                intcodes[dst_addr] = if src0 == src1 { 1 } else { 0 };
-                pc += 4;
-            }
-            99 => {
-                // Reached the end; exit.
-                break;
-            }
-            _ => {
-                return error(format!("Invalid opcode: {}", op_code));
-            }
-        }
+/// Run the program with the `sys` instruction enabled and the given limits, returning its
+/// outputs and anything it logged.
+fn run_sandboxed(
+    program: &Program,
+    inputs: Vec<IntCode>,
+    sandbox: Sandbox,
+    limits: Limits,
+) -> Res<(Vec<IntCode>, Vec<String>)> {
+    let mut registry = Registry::new(program.metadata.dialect.unwrap_or_default());
+    hostcall::register(&mut registry)?;
+    let sandbox = Arc::new(Mutex::new(sandbox));
+    let mut machine = program.machine_with_registry(Arc::new(registry));
+    machine.set_host(sandbox.clone());
+    machine.set_limits(limits);
+    for input in inputs {
+        machine.push_input(input);
     }
-    Ok(outputs)
+    machine.run()?;
+    let logs = sandbox.lock().unwrap().logs().to_vec();
+    Ok((machine.take_outputs(), logs))
+}
+
+/// Run the program with a paint robot attached.
+fn paint(program: &Program, start: Colour) -> Res<PaintRobot> {
+    let mut robot = PaintRobot::new(start);
+    run_device(&mut program.machine(), &mut robot)?;
+    Ok(robot)
 }
 
-/// Get the two operands and destination address for the given program counter.
-fn get_two_operands_and_dst(
-    intcodes: &mut Vec<IntCode>,
-    pc: usize,
-    modes: (ParameterMode, ParameterMode),
-) -> Res<(IntCode, IntCode, usize)> {
-    let (mode0, mode1) = modes;
-    let src0 = get_op(intcodes, pc + 1, mode0)?;
-    let src1 = get_op(intcodes, pc + 2, mode1)?;
-    let dst_addr = intcodes[pc + 3] as usize;
-    if dst_addr >= intcodes.len() {
-        return error(format!("Invalid dest address: {}", dst_addr));
+/// Run an arcade game with the given joystick, optionally drawing every frame.
+fn play(program: &Program, joystick: Box<dyn Joystick>, display: bool) -> Res<Arcade> {
+    let mut arcade = Arcade::new(joystick);
+    if display {
+        arcade = arcade.with_display();
     }
-    Ok((src0, src1, dst_addr))
+    run_device(&mut program.machine(), &mut arcade)?;
+    Ok(arcade)
 }
 
-fn get_op(intcodes: &mut Vec<IntCode>, addr: usize, mode: ParameterMode) -> Res<IntCode> {
-    let value = intcodes[addr];
+/// Like `run`, but through the peephole optimiser.
+fn run_optimized(program: &mut Program, inputs: Vec<IntCode>) -> Res<Vec<IntCode>> {
+    let mut machine = new_machine(program, inputs);
+    let optimized = optimize(&program.code, machine.registry());
+    println!("Optimizer: {:?}", optimized.stats);
+    optimized.run(&mut machine)?;
+    let outputs = machine.take_outputs();
+    program.code = machine.into_memory();
+    Ok(outputs)
+}
 
-    match mode {
-        ParameterMode::Position => {
-            let value = value as usize;
-            if value >= intcodes.len() {
-                return error(format!("Invalid src address: {}", value));
-            }
-            Ok(intcodes[value])
+/// Run the program, recording its I/O. Inputs beyond those given are read from stdin.
+fn record(program: &Program, inputs: Vec<IntCode>) -> Res<Session> {
+    let mut machine = new_machine(program, inputs);
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let session = Session::record(&mut machine, || {
+        println!("input?");
+        match lines.next() {
+            Some(line) => Ok(line?.trim().parse::<IntCode>()?),
+            None => error("Ran out of input."),
         }
-        ParameterMode::Immediate => Ok(value),
+    })?;
+    println!("outputs: {:?}", machine.outputs());
+    Ok(session)
+}
+
+/// Run a fresh copy of the program once per input list, merging coverage from every run.
+fn run_with_coverage(program: &Program, runs: Vec<Vec<IntCode>>) -> Res<Coverage> {
+    let mut coverage = Coverage::new();
+    for inputs in runs {
+        let mut machine = new_machine(program, inputs);
+        coverage.run(&mut machine)?;
+        println!("outputs: {:?}", machine.outputs());
     }
+    Ok(coverage)
 }
 
 #[cfg(test)]
@@ -196,68 +471,167 @@ mod test {
 
     #[test]
     fn given_example_1() {
-        let mut intcodes: Vec<IntCode> = vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
-        run(&mut intcodes, vec![]).unwrap();
-        assert_eq!(intcodes[0], 3500);
+        let mut program = Program::new(vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50]);
+        run(&mut program, vec![]).unwrap();
+        assert_eq!(program.code[0], 3500);
     }
     #[test]
     fn given_example_2() {
-        let mut intcodes: Vec<IntCode> = vec![1, 0, 0, 0, 99];
-        run(&mut intcodes, vec![]).unwrap();
-        assert_eq!(intcodes[0], 2);
+        let mut program = Program::new(vec![1, 0, 0, 0, 99]);
+        run(&mut program, vec![]).unwrap();
+        assert_eq!(program.code[0], 2);
     }
     #[test]
     fn given_example_3() {
-        let mut intcodes: Vec<IntCode> = vec![2, 3, 0, 3, 99];
-        run(&mut intcodes, vec![]).unwrap();
-        assert_eq!(intcodes[3], 6);
+        let mut program = Program::new(vec![2, 3, 0, 3, 99]);
+        run(&mut program, vec![]).unwrap();
+        assert_eq!(program.code[3], 6);
     }
     #[test]
     fn given_example_4() {
-        let mut intcodes: Vec<IntCode> = vec![2, 4, 4, 5, 99, 0];
-        run(&mut intcodes, vec![]).unwrap();
-        assert_eq!(intcodes[5], 9801);
+        let mut program = Program::new(vec![2, 4, 4, 5, 99, 0]);
+        run(&mut program, vec![]).unwrap();
+        assert_eq!(program.code[5], 9801);
     }
     #[test]
     fn given_example_5() {
-        let mut intcodes: Vec<IntCode> = vec![1, 1, 1, 4, 99, 5, 6, 0, 99];
-        run(&mut intcodes, vec![]).unwrap();
-        assert_eq!(intcodes[0], 30);
-        assert_eq!(intcodes[4], 2);
+        let mut program = Program::new(vec![1, 1, 1, 4, 99, 5, 6, 0, 99]);
+        run(&mut program, vec![]).unwrap();
+        assert_eq!(program.code[0], 30);
+        assert_eq!(program.code[4], 2);
     }
 
     #[test]
     fn cmp_equal_to_8_pos() -> Res<()> {
-        let mut intcodes: Vec<IntCode> = vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8];
-        assert_eq!(run(&mut intcodes, vec![1])?, vec![0]);
-        assert_eq!(run(&mut intcodes, vec![8])?, vec![1]);
+        let mut program = Program::new(vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8]);
+        assert_eq!(run(&mut program, vec![1])?, vec![0]);
+        assert_eq!(run(&mut program, vec![8])?, vec![1]);
         Ok(())
     }
 
     #[test]
     fn cmp_less_than_8_pos() -> Res<()> {
-        let mut intcodes: Vec<IntCode> = vec![3, 9, 7, 9, 10, 9, 4, 9, 99, -1, 8];
-        assert_eq!(run(&mut intcodes, vec![1])?, vec![1]);
-        assert_eq!(run(&mut intcodes, vec![7])?, vec![1]);
-        assert_eq!(run(&mut intcodes, vec![8])?, vec![0]);
-        assert_eq!(run(&mut intcodes, vec![20])?, vec![0]);
+        let mut program = Program::new(vec![3, 9, 7, 9, 10, 9, 4, 9, 99, -1, 8]);
+        assert_eq!(run(&mut program, vec![1])?, vec![1]);
+        assert_eq!(run(&mut program, vec![7])?, vec![1]);
+        assert_eq!(run(&mut program, vec![8])?, vec![0]);
+        assert_eq!(run(&mut program, vec![20])?, vec![0]);
         Ok(())
     }
     #[test]
     fn cmp_equal_to_8_imm() -> Res<()> {
-        let mut intcodes: Vec<IntCode> = vec![3, 3, 1108, -1, 8, 3, 4, 3, 99];
-        assert_eq!(run(&mut intcodes, vec![1])?, vec![0]);
-        assert_eq!(run(&mut intcodes, vec![8])?, vec![1]);
+        let mut program = Program::new(vec![3, 3, 1108, -1, 8, 3, 4, 3, 99]);
+        assert_eq!(run(&mut program, vec![1])?, vec![0]);
+        assert_eq!(run(&mut program, vec![8])?, vec![1]);
         Ok(())
     }
 
     #[test]
     fn cmp_less_than_8_imm() -> Res<()> {
-        let mut intcodes: Vec<IntCode> = vec![3, 3, 1107, -1, 8, 3, 4, 3, 99];
-        assert_eq!(run(&mut intcodes, vec![1])?, vec![1]);
-        assert_eq!(run(&mut intcodes, vec![7])?, vec![1]);
-        assert_eq!(run(&mut intcodes, vec![8])?, vec![0]);
-        assert_eq!(run(&mut intcodes, vec![20])?, vec![0]);
+        let mut program = Program::new(vec![3, 3, 1107, -1, 8, 3, 4, 3, 99]);
+        assert_eq!(run(&mut program, vec![1])?, vec![1]);
+        assert_eq!(run(&mut program, vec![7])?, vec![1]);
+        assert_eq!(run(&mut program, vec![8])?, vec![0]);
+        assert_eq!(run(&mut program, vec![20])?, vec![0]);
+        Ok(())
+    }
+
+    #[test]
+    fn dialects() -> Res<()> {
+        let mut program = Program::new(vec![109, 3, 204, 0, 99]);
+        assert!(run(&mut program, vec![]).is_err());
+        program.metadata.dialect = Some(Dialect::Full);
+        assert_eq!(run(&mut program, vec![])?, vec![0]);
+        Ok(())
+    }
+
+    #[test]
+    fn starts_at_entry() -> Res<()> {
+        // From 2, outputs the input doubled; from 0, outputs 1 first.
+        let source = "@entry 2\n104,1,3,0,1002,0,2,0,4,0,99";
+        let mut program = Program::parse(source)?;
+        assert_eq!(run(&mut program.clone(), vec![21])?, vec![42]);
+        assert_eq!(run_optimized(&mut program.clone(), vec![21])?, vec![42]);
+        assert_eq!(record(&program, vec![21])?.events.len(), 2);
+        let sandbox = Sandbox::new();
+        assert_eq!(
+            run_sandboxed(&program, vec![21], sandbox, Limits::default())?.0,
+            vec![42]
+        );
+        program.metadata.entry = 0;
+        assert_eq!(run(&mut program, vec![21])?, vec![1, 42]);
+        Ok(())
+    }
+
+    #[test]
+    fn sandboxed_host_calls() -> Res<()> {
+        // sys 3, 9, 2, 0, [0]; out 42; hlt; "ok"
+        let program = Program::new(vec![111180, 3, 9, 2, 0, 0, 104, 42, 99, 111, 107]);
+        assert!(run_sandboxed(&program, vec![], Sandbox::new(), Limits::default()).is_err());
+        let sandbox = Sandbox::new().allow_log();
+        let (outputs, logs) = run_sandboxed(&program, vec![], sandbox, Limits::default())?;
+        assert_eq!(outputs, vec![42]);
+        assert_eq!(logs, vec!["ok".to_string()]);
+
+        // The sys instruction writes its result over its own opcode.
+        let limits = Limits::default().with_read_only(0..9);
+        let sandbox = Sandbox::new().allow_log();
+        assert!(run_sandboxed(&program, vec![], sandbox, limits).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn paint_robot() -> Res<()> {
+        // in [11]; out [11]; out 1; in [11]; out 1; hlt (the last paint never gets a turn)
+        let intcodes: Vec<IntCode> = vec![3, 11, 4, 11, 104, 1, 3, 11, 104, 1, 99, 0];
+        let robot = paint(&Program::new(intcodes), Colour::White)?;
+        assert_eq!(robot.painted(), 1);
+        assert_eq!(robot.render(), "#\n");
+        Ok(())
+    }
+
+    #[test]
+    fn arcade() -> Res<()> {
+        // Draws a paddle at (1, 2), reads the joystick and shows the reading as the score.
+        let intcodes: Vec<IntCode> = vec![
+            104, 1, 104, 2, 104, 3, 3, 21, 104, -1, 104, 0, 4, 21, 99, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let arcade = play(&Program::new(intcodes), Box::new(TrackBall), false)?;
+        assert_eq!(arcade.frames(), 1);
+        assert_eq!(arcade.score(), 0);
+        assert_eq!(arcade.screen().render(), "_\nScore: 0\n");
+        Ok(())
+    }
+
+    #[test]
+    fn optimized_matches_interpreter() -> Res<()> {
+        let program = Program::new(vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8]);
+        for &input in &[1, 8, 20] {
+            let mut plain = program.clone();
+            let mut optimized = program.clone();
+            assert_eq!(
+                run(&mut plain, vec![input])?,
+                run_optimized(&mut optimized, vec![input])?
+            );
+            assert_eq!(plain, optimized);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn coverage_across_runs() -> Res<()> {
+        let intcodes: Vec<IntCode> = vec![
+            3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0,
+            0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4,
+            20, 1105, 1, 46, 98, 99,
+        ];
+        let program = Program::new(intcodes.clone());
+        let coverage = run_with_coverage(&program, vec![vec![7]])?;
+        assert!(!coverage.uncovered(&intcodes).is_empty());
+
+        let coverage = run_with_coverage(&program, vec![vec![7], vec![8], vec![9]])?;
+        assert_eq!(coverage.hits(0), 3);
+        assert!(coverage.uncovered(&intcodes).is_empty());
         Ok(())
     }
 }
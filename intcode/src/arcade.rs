@@ -0,0 +1,272 @@
+use crate::device::Device;
+use crate::grid::{bounds, Position};
+use crate::patch::{patch, Patch};
+use crate::IntCode;
+use common::{error, Res};
+use std::collections::HashMap;
+use std::io::BufRead;
+
+/*
+ * The arcade cabinet.
+ *
+ * The program draws by outputting (x, y, tile) triples; the triple (-1, 0, score) sets the
+ * score instead. Whenever it reads input, it wants the joystick position: -1 left, 0 neutral
+ * or 1 right. Every input request therefore marks a complete frame.
+ */
+
+/// Writing 2 to address 0 lets the game be played without inserting quarters.
+pub const FREE_PLAY: Patch = (0, 2);
+
+/// Copy the program with free play enabled.
+pub fn free_play(program: &[IntCode]) -> Res<Vec<IntCode>> {
+    patch(program, &[FREE_PLAY])
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Tile {
+    Empty,
+    Wall,
+    Block,
+    Paddle,
+    Ball,
+}
+
+impl Tile {
+    fn from_int(value: IntCode) -> Res<Tile> {
+        Ok(match value {
+            0 => Tile::Empty,
+            1 => Tile::Wall,
+            2 => Tile::Block,
+            3 => Tile::Paddle,
+            4 => Tile::Ball,
+            _ => return error(format!("Invalid tile: {}", value)),
+        })
+    }
+
+    fn symbol(self) -> char {
+        match self {
+            Tile::Empty => ' ',
+            Tile::Wall => '#',
+            Tile::Block => '=',
+            Tile::Paddle => '_',
+            Tile::Ball => 'o',
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Screen {
+    tiles: HashMap<Position, Tile>,
+    pub score: IntCode,
+}
+
+impl Screen {
+    pub fn tile(&self, position: Position) -> Tile {
+        self.tiles.get(&position).cloned().unwrap_or(Tile::Empty)
+    }
+
+    pub fn count(&self, tile: Tile) -> usize {
+        self.tiles.values().filter(|&&t| t == tile).count()
+    }
+
+    /// Where the given tile is drawn, if anywhere. For tiles drawn more than once, any one of
+    /// their positions.
+    pub fn find(&self, tile: Tile) -> Option<Position> {
+        self.tiles
+            .iter()
+            .find(|&(_, &t)| t == tile)
+            .map(|(&position, _)| position)
+    }
+
+    /// The screen as text, followed by the score.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        if let Some(((min_x, min_y), (max_x, max_y))) = bounds(self.tiles.keys()) {
+            for y in min_y..=max_y {
+                for x in min_x..=max_x {
+                    out.push(self.tile((x, y)).symbol());
+                }
+                out.push('\n');
+            }
+        }
+        out.push_str(&format!("Score: {}\n", self.score));
+        out
+    }
+}
+
+/// Decides where to push the joystick for each frame.
+pub trait Joystick {
+    fn tilt(&mut self, screen: &Screen) -> Res<IntCode>;
+}
+
+/// Keeps the paddle under the ball.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TrackBall;
+
+impl Joystick for TrackBall {
+    fn tilt(&mut self, screen: &Screen) -> Res<IntCode> {
+        Ok(match (screen.find(Tile::Ball), screen.find(Tile::Paddle)) {
+            (Some(ball), Some(paddle)) => (ball.0 - paddle.0).signum(),
+            _ => 0,
+        })
+    }
+}
+
+/// Reads moves from lines of text: `a` or `h` for left, `d` or `l` for right, anything else
+/// for neutral.
+pub struct Keyboard<R: BufRead> {
+    reader: R,
+}
+
+impl<R: BufRead> Keyboard<R> {
+    pub fn new(reader: R) -> Keyboard<R> {
+        Keyboard { reader }
+    }
+}
+
+impl<R: BufRead> Joystick for Keyboard<R> {
+    fn tilt(&mut self, _screen: &Screen) -> Res<IntCode> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return error("Ran out of input.");
+        }
+        Ok(match line.trim() {
+            "a" | "h" => -1,
+            "d" | "l" => 1,
+            _ => 0,
+        })
+    }
+}
+
+pub struct Arcade {
+    screen: Screen,
+    joystick: Box<dyn Joystick>,
+    /// Output values not yet making up a whole triple.
+    pending: Vec<IntCode>,
+    frames: usize,
+    /// Print each frame to the terminal before asking the joystick.
+    display: bool,
+}
+
+impl Arcade {
+    pub fn new(joystick: Box<dyn Joystick>) -> Arcade {
+        Arcade {
+            screen: Screen::default(),
+            joystick,
+            pending: vec![],
+            frames: 0,
+            display: false,
+        }
+    }
+
+    pub fn with_display(mut self) -> Arcade {
+        self.display = true;
+        self
+    }
+
+    pub fn screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    pub fn score(&self) -> IntCode {
+        self.screen.score
+    }
+
+    /// Frames completed so far, i.e. joystick reads.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+}
+
+impl Device for Arcade {
+    fn input(&mut self) -> Res<IntCode> {
+        self.frames += 1;
+        if self.display {
+            // Clear the terminal and draw from the top.
+            print!("\x1b[2J\x1b[H{}", self.screen.render());
+        }
+        self.joystick.tilt(&self.screen)
+    }
+
+    fn output(&mut self, value: IntCode) -> Res<()> {
+        self.pending.push(value);
+        if let [x, y, value] = self.pending[..] {
+            self.pending.clear();
+            if (x, y) == (-1, 0) {
+                self.screen.score = value;
+            } else {
+                self.screen.tiles.insert((x, y), Tile::from_int(value)?);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::device::run_device;
+    use crate::machine::Machine;
+    use crate::registry::Registry;
+    use crate::Dialect;
+
+    #[test]
+    fn screen() -> Res<()> {
+        let mut arcade = Arcade::new(Box::new(TrackBall));
+        for &value in &[1, 2, 3, 6, 5, 4, 0, 0, 1, -1, 0, 12345] {
+            arcade.output(value)?;
+        }
+        assert_eq!(arcade.screen().tile((1, 2)), Tile::Paddle);
+        assert_eq!(arcade.screen().count(Tile::Ball), 1);
+        assert_eq!(arcade.score(), 12345);
+        assert_eq!(arcade.screen().find(Tile::Ball), Some((6, 5)));
+        assert_eq!(arcade.input()?, 1);
+        assert!(arcade.output(0).is_ok());
+        assert!(arcade.output(0).is_ok());
+        assert!(arcade.output(9).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn free_play_and_joystick() -> Res<()> {
+        // Like the real games, free play turns the first instruction from an add into a
+        // multiply. With it, draws a paddle at x=1 and a ball at x=3, then scores the
+        // joystick reading plus one; without it, scores 0 without asking.
+        let source = "
+                    add [three], [three], [coin]
+                    eq [coin], 9, [coin]
+                    jf [coin], over
+                    out 1
+                    out 0
+                    out 3
+                    out 3
+                    out 0
+                    out 4
+                    in [tilt]
+                    add [tilt], 1, [tilt]
+            over:   out -1
+                    out 0
+                    out [tilt]
+                    hlt
+            coin:   data 0
+            tilt:   data 0
+            three:  data 3
+        ";
+        let program = assemble(source, &Registry::new(Dialect::Day05))?;
+        let mut arcade = Arcade::new(Box::new(TrackBall));
+        run_device(&mut Machine::new(program.clone()), &mut arcade)?;
+        assert_eq!((arcade.frames(), arcade.score()), (0, 0));
+
+        let mut arcade = Arcade::new(Box::new(TrackBall));
+        run_device(&mut Machine::new(free_play(&program)?), &mut arcade)?;
+        assert_eq!((arcade.frames(), arcade.score()), (1, 2));
+        assert_eq!(arcade.screen().render(), "_ o\nScore: 2\n");
+
+        let keys = Keyboard::new("a\n".as_bytes());
+        let mut arcade = Arcade::new(Box::new(keys));
+        run_device(&mut Machine::new(free_play(&program)?), &mut arcade)?;
+        assert_eq!(arcade.score(), 0);
+        Ok(())
+    }
+}
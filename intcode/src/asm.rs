@@ -0,0 +1,312 @@
+use crate::instruction::{Instruction, ParameterMode, Role};
+use crate::loader::LoadError;
+use crate::registry::Registry;
+use crate::IntCode;
+use std::collections::HashMap;
+
+/*
+ * Intcode assembly.
+ *
+ * One instruction per line, written the way instructions are displayed: a mnemonic from the
+ * registry followed by comma-separated operands, `[addr]` for position mode, a bare value
+ * for immediate mode and `[rb+offset]` for relative mode. Any value may be a label, defined
+ * by `name:` at the start of a line. `data` emits its operands as raw cells, and `#` starts
+ * a comment:
+ *
+ *     loop:   in [x]
+ *             out [x]
+ *             jt [x], loop
+ *             hlt
+ *     x:      data 0
+ */
+
+struct Line<'a> {
+    number: usize,
+    /// Column of the mnemonic, counting from 0.
+    column: usize,
+    mnemonic: &'a str,
+    operands: Vec<(usize, &'a str)>,
+}
+
+/// Assemble a program for the given registry.
+pub fn assemble(source: &str, registry: &Registry) -> Result<Vec<IntCode>, LoadError> {
+    // First pass: find the labels and the address of every line.
+    let mut labels: HashMap<&str, IntCode> = HashMap::new();
+    let mut lines = vec![];
+    let mut addr = 0;
+    for (index, text) in source.lines().enumerate() {
+        let mut code = match text.find('#') {
+            Some(end) => &text[..end],
+            None => text,
+        };
+        let mut column = 0;
+        if let Some(colon) = code.find(':') {
+            let label = code[..colon].trim();
+            if label.is_empty() || !label.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                return Err(syntax_error(
+                    index + 1,
+                    0,
+                    format!("invalid label \"{}\"", label),
+                ));
+            }
+            if labels.insert(label, addr).is_some() {
+                return Err(syntax_error(
+                    index + 1,
+                    0,
+                    format!("duplicate label \"{}\"", label),
+                ));
+            }
+            column = colon + 1;
+            code = &text[column..code.len()];
+        }
+        let mnemonic = code.trim_start();
+        if mnemonic.trim().is_empty() {
+            continue;
+        }
+        column += code.len() - mnemonic.len();
+        let (mnemonic, rest) = match mnemonic.find(char::is_whitespace) {
+            Some(end) => (&mnemonic[..end], &mnemonic[end..]),
+            None => (mnemonic.trim_end(), ""),
+        };
+        let mut operands = vec![];
+        let mut start = column + mnemonic.len();
+        if !rest.trim().is_empty() {
+            for operand in rest.split(',') {
+                let trimmed = operand.trim_start();
+                operands.push((start + operand.len() - trimmed.len(), trimmed.trim_end()));
+                start += operand.len() + 1;
+            }
+        }
+        let line = Line {
+            number: index + 1,
+            column,
+            mnemonic,
+            operands,
+        };
+        addr += match line.mnemonic {
+            "data" => line.operands.len(),
+            _ => 1 + line.operands.len(),
+        } as IntCode;
+        lines.push(line);
+    }
+
+    // Second pass: encode.
+    let mut program = vec![];
+    for line in &lines {
+        let error = |column: usize, message: String| syntax_error(line.number, column, message);
+        let value = |column: usize, text: &str| -> Result<IntCode, LoadError> {
+            if let Some(&addr) = labels.get(text) {
+                return Ok(addr);
+            }
+            text.parse()
+                .map_err(|_| error(column, format!("invalid value \"{}\"", text)))
+        };
+
+        if line.mnemonic == "data" {
+            for &(column, operand) in &line.operands {
+                program.push(value(column, operand)?);
+            }
+            continue;
+        }
+        let spec = match registry.by_mnemonic(line.mnemonic) {
+            Some(spec) => spec,
+            None => {
+                return Err(error(
+                    line.column,
+                    format!("unknown instruction \"{}\"", line.mnemonic),
+                ))
+            }
+        };
+        if line.operands.len() != spec.roles.len() {
+            return Err(error(
+                line.column,
+                format!(
+                    "{} takes {} operands, got {}",
+                    spec.mnemonic,
+                    spec.roles.len(),
+                    line.operands.len()
+                ),
+            ));
+        }
+
+        let mut opcode = spec.opcode.code();
+        let mut values = vec![];
+        let mut scale = 100;
+        for (&(column, operand), &role) in line.operands.iter().zip(&spec.roles) {
+            let (mode, operand_value) = match operand.strip_prefix('[') {
+                Some(inner) => {
+                    let inner = match inner.strip_suffix(']') {
+                        Some(inner) => inner.trim(),
+                        None => return Err(error(column, "missing \"]\"".to_string())),
+                    };
+                    match inner.strip_prefix("rb") {
+                        Some(offset) => {
+                            let offset = offset.trim_start();
+                            let negative = offset.starts_with('-');
+                            let invalid = || error(column, format!("invalid offset \"{}\"", inner));
+                            // The sign comes from the `+` or `-`, so the magnitude can't have one.
+                            let magnitude = match offset.strip_prefix(['+', '-']) {
+                                Some(magnitude) if !magnitude.trim().starts_with(['+', '-']) => {
+                                    value(column, magnitude.trim())?
+                                }
+                                _ => return Err(invalid()),
+                            };
+                            let offset = if negative {
+                                match magnitude.checked_neg() {
+                                    Some(offset) => offset,
+                                    None => return Err(invalid()),
+                                }
+                            } else {
+                                magnitude
+                            };
+                            (ParameterMode::Relative, offset)
+                        }
+                        None => (ParameterMode::Position, value(column, inner)?),
+                    }
+                }
+                None => {
+                    if role == Role::Write {
+                        return Err(error(
+                            column,
+                            format!("{} cannot write to an immediate", spec.mnemonic),
+                        ));
+                    }
+                    (ParameterMode::Immediate, value(column, operand)?)
+                }
+            };
+            if !registry.dialect().supports_mode(mode) {
+                return Err(error(
+                    column,
+                    format!("{} has no {:?} mode", registry.dialect(), mode),
+                ));
+            }
+            opcode += mode.digit() * scale;
+            scale *= 10;
+            values.push(operand_value);
+        }
+        program.push(opcode);
+        program.extend(values);
+    }
+    Ok(program)
+}
+
+/// The cells an instruction was decoded from, as far as they can be recovered: write
+/// parameters decoded from an immediate mode digit come back in position mode.
+pub fn encode(instruction: &Instruction) -> Vec<IntCode> {
+    let mut opcode = instruction.opcode.code();
+    let mut scale = 100;
+    for param in &instruction.params {
+        opcode += param.mode.digit() * scale;
+        scale *= 10;
+    }
+    let mut cells = vec![opcode];
+    cells.extend(instruction.params.iter().map(|param| param.value));
+    cells
+}
+
+fn syntax_error(line: usize, column: usize, message: String) -> LoadError {
+    LoadError::Text {
+        line,
+        column: column + 1,
+        message,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::instruction::Dialect;
+    use crate::machine::Machine;
+    use std::sync::Arc;
+
+    #[test]
+    fn assemble_program() -> Result<(), LoadError> {
+        let registry = Registry::new(Dialect::Day05);
+        let source = "
+            # Echo inputs until a zero.
+            loop:   in [x]
+                    out [x]
+                    jt [x], loop
+                    hlt
+            x:      data 0
+        ";
+        let program = assemble(source, &registry)?;
+        assert_eq!(program, vec![3, 8, 4, 8, 1005, 8, 0, 99, 0]);
+
+        let mut machine = Machine::with_inputs(program, vec![4, 2, 0]);
+        machine.run().unwrap();
+        assert_eq!(machine.outputs(), &[4, 2, 0]);
+
+        let full = Registry::new(Dialect::Full);
+        assert_eq!(
+            assemble("add [rb-3], 7, [rb+2]", &full)?,
+            vec![21201, -3, 7, 2]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn assembly_errors() {
+        let registry = Registry::new(Dialect::Day05);
+        let error = |source| assemble(source, &registry).unwrap_err().to_string();
+        assert_eq!(
+            error("  add 1, 2, 3"),
+            "line 1, column 13: add cannot write to an immediate"
+        );
+        assert_eq!(
+            error("nop"),
+            "line 1, column 1: unknown instruction \"nop\""
+        );
+        assert_eq!(
+            error("out 1, 2"),
+            "line 1, column 1: out takes 1 operands, got 2"
+        );
+        assert_eq!(error("out [x]"), "line 1, column 5: invalid value \"x\"");
+        assert_eq!(
+            error("a: hlt\na: hlt"),
+            "line 2, column 1: duplicate label \"a\""
+        );
+        assert_eq!(
+            error("out [rb+1]"),
+            "line 1, column 5: day05 has no Relative mode"
+        );
+        let full = Registry::new(Dialect::Full);
+        let error = |source| assemble(source, &full).unwrap_err().to_string();
+        assert_eq!(
+            error("out [rb+-5]"),
+            "line 1, column 5: invalid offset \"rb+-5\""
+        );
+        assert_eq!(
+            error("out [rb- -5]"),
+            "line 1, column 5: invalid offset \"rb- -5\""
+        );
+        assert_eq!(
+            error("out [rb--9223372036854775808]"),
+            "line 1, column 5: invalid offset \"rb--9223372036854775808\""
+        );
+        assert_eq!(
+            error("out [rb5]"),
+            "line 1, column 5: invalid offset \"rb5\""
+        );
+    }
+
+    #[test]
+    fn custom_instructions() -> Result<(), LoadError> {
+        fn double(context: &mut crate::registry::Context) -> common::Res<()> {
+            let value = context.arg(0)?;
+            context.store(1, value * 2)
+        }
+        let mut registry = Registry::new(Dialect::Day05);
+        registry
+            .register(42, "dbl", &[Role::Read, Role::Write], double)
+            .unwrap();
+        let registry = Arc::new(registry);
+        let program = assemble("dbl 21, [x]\nout [x]\nhlt\nx: data 0", &registry)?;
+        assert_eq!(program, vec![142, 21, 6, 4, 6, 99, 0]);
+
+        let mut machine = Machine::with_registry(program, registry);
+        machine.run().unwrap();
+        assert_eq!(machine.outputs(), &[42]);
+        Ok(())
+    }
+}
@@ -0,0 +1,302 @@
+use crate::instruction::{Dialect, Opcode};
+use crate::machine::{Machine, Step};
+use crate::registry::Registry;
+use crate::IntCode;
+use common::Res;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// How often a conditional jump went each way. Comparisons count as branches too, with
+/// `taken` counting the times they held.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct BranchCounts {
+    pub taken: usize,
+    pub not_taken: usize,
+}
+
+/// Per-address execution counts, merged across any number of runs of one program.
+#[derive(Clone, Debug)]
+pub struct Coverage {
+    /// Used to disassemble the program for reports; taken from the last machine run.
+    registry: Arc<Registry>,
+    hits: BTreeMap<usize, usize>,
+    branches: BTreeMap<usize, BranchCounts>,
+}
+
+impl Default for Coverage {
+    fn default() -> Coverage {
+        Coverage::new()
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage {
+            registry: Arc::new(Registry::new(Dialect::default())),
+            hits: BTreeMap::new(),
+            branches: BTreeMap::new(),
+        }
+    }
+
+    /// Run the machine to completion, recording every executed instruction.
+    pub fn run(&mut self, machine: &mut Machine) -> Res<()> {
+        self.registry = machine.registry().clone();
+        machine.run_with(|step| self.record(step))
+    }
+
+    pub fn record(&mut self, step: &Step) {
+        *self.hits.entry(step.pc).or_insert(0) += 1;
+        if let Some(taken) = step.branch {
+            let counts = self.branches.entry(step.pc).or_default();
+            if taken {
+                counts.taken += 1;
+            } else {
+                counts.not_taken += 1;
+            }
+        }
+    }
+
+    /// Fold another report into this one.
+    pub fn merge(&mut self, other: &Coverage) {
+        for (&pc, &hits) in &other.hits {
+            *self.hits.entry(pc).or_insert(0) += hits;
+        }
+        for (&pc, counts) in &other.branches {
+            let merged = self.branches.entry(pc).or_default();
+            merged.taken += counts.taken;
+            merged.not_taken += counts.not_taken;
+        }
+    }
+
+    pub fn hits(&self, pc: usize) -> usize {
+        self.hits.get(&pc).cloned().unwrap_or(0)
+    }
+
+    pub fn branch(&self, pc: usize) -> Option<BranchCounts> {
+        self.branches.get(&pc).cloned()
+    }
+
+    /// Address ranges of instructions that never ran.
+    pub fn uncovered(&self, program: &[IntCode]) -> Vec<Range<usize>> {
+        let mut ranges: Vec<Range<usize>> = vec![];
+        for (pc, size) in self.instructions(program) {
+            if self.hits(pc) > 0 {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some(last) if last.end == pc => last.end = pc + size,
+                _ => ranges.push(pc..pc + size),
+            }
+        }
+        ranges
+    }
+
+    /// Disassembly of the program annotated with hit counts, gcov style: `#####` marks
+    /// instructions that never ran and `-` marks data.
+    pub fn listing(&self, program: &[IntCode]) -> String {
+        let instructions: BTreeMap<usize, usize> = self.instructions(program).into_iter().collect();
+        let mut out = String::new();
+        let mut pc = 0;
+        while pc < program.len() {
+            match instructions.get(&pc) {
+                Some(&size) => {
+                    let instruction = self.registry.decode(program, pc).unwrap();
+                    let hits = match self.hits(pc) {
+                        0 => "#####".to_string(),
+                        hits => hits.to_string(),
+                    };
+                    let _ = write!(out, "{:>8}: {:>5}: {}", hits, pc, instruction);
+                    if let Some(counts) = self.branch(pc) {
+                        let (taken, not_taken) = match instruction.opcode {
+                            Opcode::LessThan | Opcode::Equals => ("true", "false"),
+                            _ => ("taken", "not taken"),
+                        };
+                        let _ = write!(
+                            out,
+                            "  ({} {}, {} {})",
+                            taken, counts.taken, not_taken, counts.not_taken
+                        );
+                    }
+                    out.push('\n');
+                    pc += size;
+                }
+                None => {
+                    let _ = writeln!(out, "{:>8}: {:>5}: {}", "-", pc, program[pc]);
+                    pc += 1;
+                }
+            }
+        }
+        out
+    }
+
+    /// LCOV tracefile, using addresses in place of line numbers.
+    pub fn lcov(&self, program: &[IntCode], name: &str) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "TN:\nSF:{}", name);
+
+        let instructions = self.instructions(program);
+        let mut branches_found = 0;
+        let mut branches_hit = 0;
+        for &(pc, _) in &instructions {
+            let opcode = self.registry.decode(program, pc).unwrap().opcode;
+            match opcode {
+                Opcode::JumpIfTrue | Opcode::JumpIfFalse | Opcode::LessThan | Opcode::Equals => {}
+                _ => continue,
+            }
+            let hits = self.hits(pc);
+            let counts = self.branch(pc).unwrap_or_default();
+            for (index, count) in [counts.taken, counts.not_taken].iter().enumerate() {
+                branches_found += 1;
+                if *count > 0 {
+                    branches_hit += 1;
+                }
+                if hits == 0 {
+                    let _ = writeln!(out, "BRDA:{},0,{},-", pc, index);
+                } else {
+                    let _ = writeln!(out, "BRDA:{},0,{},{}", pc, index, count);
+                }
+            }
+        }
+        let _ = writeln!(out, "BRF:{}\nBRH:{}", branches_found, branches_hit);
+
+        for &(pc, _) in &instructions {
+            let _ = writeln!(out, "DA:{},{}", pc, self.hits(pc));
+        }
+        let lines_hit = instructions
+            .iter()
+            .filter(|(pc, _)| self.hits(*pc) > 0)
+            .count();
+        let _ = writeln!(out, "LF:{}\nLH:{}", instructions.len(), lines_hit);
+        out.push_str("end_of_record\n");
+        out
+    }
+
+    /// Addresses and sizes of the instructions in the program: everything that was executed,
+    /// plus whatever a linear sweep decodes in between. Cells that fail to decode are data.
+    fn instructions(&self, program: &[IntCode]) -> Vec<(usize, usize)> {
+        let mut instructions = vec![];
+        let mut pc = 0;
+        while pc < program.len() {
+            let size = match self.registry.decode(program, pc) {
+                Ok(instruction) => instruction.size(),
+                Err(_) => {
+                    pc += 1;
+                    continue;
+                }
+            };
+            // Don't let the sweep swallow an instruction that actually ran.
+            let next_hit = self.hits.range(pc + 1..).next().map(|(&hit, _)| hit);
+            if self.hits(pc) == 0 && next_hit.is_some_and(|hit| hit < pc + size) {
+                pc += 1;
+                continue;
+            }
+            instructions.push((pc, size));
+            pc += size;
+        }
+        instructions
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Outputs 0 if the input was 0, otherwise 1.
+    const JUMP_TEST: [IntCode; 16] = [3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, -1, 0, 1, 9];
+
+    // Outputs 1 if the input was 8, otherwise 0.
+    const CMP_TEST: [IntCode; 11] = [3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8];
+
+    fn coverage_for(inputs: &[IntCode]) -> Res<Coverage> {
+        coverage_of(&JUMP_TEST, inputs)
+    }
+
+    fn coverage_of(program: &[IntCode], inputs: &[IntCode]) -> Res<Coverage> {
+        let mut coverage = Coverage::new();
+        for &input in inputs {
+            let mut machine = Machine::with_inputs(program.to_vec(), vec![input]);
+            coverage.run(&mut machine)?;
+        }
+        Ok(coverage)
+    }
+
+    #[test]
+    fn single_run() -> Res<()> {
+        let coverage = coverage_for(&[0])?;
+        assert_eq!(coverage.hits(0), 1);
+        assert_eq!(
+            coverage.branch(2),
+            Some(BranchCounts {
+                taken: 1,
+                not_taken: 0
+            })
+        );
+        assert_eq!(coverage.uncovered(&JUMP_TEST), vec![5..9]);
+        Ok(())
+    }
+
+    #[test]
+    fn merged_runs() -> Res<()> {
+        let mut coverage = coverage_for(&[0])?;
+        coverage.merge(&coverage_for(&[5, 7])?);
+        assert_eq!(coverage.hits(0), 3);
+        assert_eq!(
+            coverage.branch(2),
+            Some(BranchCounts {
+                taken: 1,
+                not_taken: 2
+            })
+        );
+        assert!(coverage.uncovered(&JUMP_TEST).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn reports() -> Res<()> {
+        let coverage = coverage_for(&[0])?;
+        let listing = coverage.listing(&JUMP_TEST);
+        assert!(listing.contains("       1:     2: jf [12], [15]  (taken 1, not taken 0)\n"));
+        assert!(listing.contains("   #####:     5: add [13], [14], [13]\n"));
+        assert!(listing.contains("       -:    12: -1\n"));
+
+        let lcov = coverage.lcov(&JUMP_TEST, "jump_test");
+        assert!(lcov.starts_with("TN:\nSF:jump_test\n"));
+        assert!(lcov.contains("BRDA:2,0,0,1\nBRDA:2,0,1,0\nBRF:2\nBRH:1\n"));
+        assert!(lcov.contains("DA:5,0\n"));
+        assert!(lcov.ends_with("end_of_record\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn comparisons() -> Res<()> {
+        let coverage = coverage_of(&CMP_TEST, &[8])?;
+        assert_eq!(
+            coverage.branch(2),
+            Some(BranchCounts {
+                taken: 1,
+                not_taken: 0
+            })
+        );
+        assert!(coverage
+            .lcov(&CMP_TEST, "cmp_test")
+            .contains("BRDA:2,0,0,1\nBRDA:2,0,1,0\nBRF:2\nBRH:1\n"));
+
+        let coverage = coverage_of(&CMP_TEST, &[8, 5, 9])?;
+        assert_eq!(
+            coverage.branch(2),
+            Some(BranchCounts {
+                taken: 1,
+                not_taken: 2
+            })
+        );
+        assert!(coverage
+            .listing(&CMP_TEST)
+            .contains("       3:     2: eq [9], [10], [9]  (true 1, false 2)\n"));
+        assert!(coverage
+            .lcov(&CMP_TEST, "cmp_test")
+            .contains("BRDA:2,0,0,1\nBRDA:2,0,1,2\nBRF:2\nBRH:2\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,27 @@
+use crate::machine::Machine;
+use crate::IntCode;
+use common::Res;
+
+/// A peripheral connected to a machine's input and output.
+pub trait Device {
+    /// Called whenever the program wants input.
+    fn input(&mut self) -> Res<IntCode>;
+
+    /// Called with every value the program outputs.
+    fn output(&mut self, value: IntCode) -> Res<()>;
+}
+
+/// Run the machine to completion with the device attached. Queued inputs are used up before
+/// the device is asked for more.
+pub fn run_device<D: Device + ?Sized>(machine: &mut Machine, device: &mut D) -> Res<()> {
+    while !machine.is_halted() {
+        if machine.needs_input() {
+            machine.push_input(device.input()?);
+        }
+        machine.step()?;
+        for value in machine.take_outputs() {
+            device.output(value)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,143 @@
+use crate::machine::{Machine, Step, Write};
+use crate::IntCode;
+use common::Res;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Memory contents along with the pc of the last instruction to write each cell.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Snapshot {
+    pub memory: Vec<IntCode>,
+    pub writers: BTreeMap<usize, usize>,
+}
+
+impl Snapshot {
+    /// A snapshot of memory that nothing has written to yet.
+    pub fn new(memory: &[IntCode]) -> Snapshot {
+        Snapshot {
+            memory: memory.to_vec(),
+            writers: BTreeMap::new(),
+        }
+    }
+
+    /// Run the machine to completion and snapshot the final memory.
+    pub fn run(machine: &mut Machine) -> Res<Snapshot> {
+        let mut writers = BTreeMap::new();
+        machine.run_with(|step| {
+            if let Some(write) = step.write {
+                writers.insert(write.addr, step.pc);
+            }
+        })?;
+        Ok(Snapshot {
+            memory: machine.memory().to_vec(),
+            writers,
+        })
+    }
+
+    pub fn writer(&self, addr: usize) -> Option<usize> {
+        self.writers.get(&addr).cloned()
+    }
+}
+
+/// A cell that differs between two snapshots.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Change {
+    pub addr: usize,
+    pub before: IntCode,
+    pub after: IntCode,
+    pub before_writer: Option<usize>,
+    pub after_writer: Option<usize>,
+}
+
+/// Every cell whose value differs. Cells missing from the shorter snapshot read as 0.
+pub fn diff(before: &Snapshot, after: &Snapshot) -> Vec<Change> {
+    let len = before.memory.len().max(after.memory.len());
+    (0..len)
+        .filter_map(|addr| {
+            let old = before.memory.get(addr).cloned().unwrap_or(0);
+            let new = after.memory.get(addr).cloned().unwrap_or(0);
+            if old == new {
+                return None;
+            }
+            Some(Change {
+                addr,
+                before: old,
+                after: new,
+                before_writer: before.writer(addr),
+                after_writer: after.writer(addr),
+            })
+        })
+        .collect()
+}
+
+/// Run the machine to completion, calling `on_change` for every write that changes the value
+/// of a watched address.
+pub fn watch<F: FnMut(&Step, &Write)>(
+    machine: &mut Machine,
+    addrs: &[usize],
+    mut on_change: F,
+) -> Res<()> {
+    let addrs: BTreeSet<usize> = addrs.iter().cloned().collect();
+    machine.run_with(|step| {
+        if let Some(write) = &step.write {
+            if write.old != write.new && addrs.contains(&write.addr) {
+                on_change(step, write);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PROGRAM: [IntCode; 12] = [1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
+
+    #[test]
+    fn diff_before_and_after() -> Res<()> {
+        let before = Snapshot::new(&PROGRAM);
+        let after = Snapshot::run(&mut Machine::new(PROGRAM.to_vec()))?;
+        let changes = diff(&before, &after);
+        assert_eq!(
+            changes,
+            vec![
+                Change {
+                    addr: 0,
+                    before: 1,
+                    after: 3500,
+                    before_writer: None,
+                    after_writer: Some(4),
+                },
+                Change {
+                    addr: 3,
+                    before: 3,
+                    after: 70,
+                    before_writer: None,
+                    after_writer: Some(0),
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn diff_two_runs() -> Res<()> {
+        let mut patched = PROGRAM.to_vec();
+        patched[10] = 41;
+        let first = Snapshot::run(&mut Machine::new(PROGRAM.to_vec()))?;
+        let second = Snapshot::run(&mut Machine::new(patched))?;
+        let addrs: Vec<usize> = diff(&first, &second).iter().map(|c| c.addr).collect();
+        assert_eq!(addrs, vec![0, 3, 10]);
+        Ok(())
+    }
+
+    #[test]
+    fn watch_addresses() -> Res<()> {
+        let mut seen = vec![];
+        let mut machine = Machine::new(PROGRAM.to_vec());
+        watch(&mut machine, &[0], |step, write| {
+            seen.push((step.pc, write.new))
+        })?;
+        assert_eq!(seen, vec![(4, 3500)]);
+        Ok(())
+    }
+}
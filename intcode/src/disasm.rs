@@ -0,0 +1,43 @@
+use crate::asm::encode;
+use crate::registry::Registry;
+use crate::IntCode;
+use std::fmt::Write;
+
+/// Disassemble a program by linear sweep, one instruction per line with its address in a
+/// comment. Cells that don't decode, or wouldn't encode back to the same value, come out as
+/// `data`, so the listing always reassembles to the original program.
+pub fn disassemble(program: &[IntCode], registry: &Registry) -> String {
+    let mut out = String::new();
+    let mut pc = 0;
+    while pc < program.len() {
+        let (text, size) = match registry.decode(program, pc) {
+            Ok(instruction) if encode(&instruction) == program[pc..pc + instruction.size()] => {
+                (instruction.to_string(), instruction.size())
+            }
+            _ => (format!("data {}", program[pc]), 1),
+        };
+        let _ = writeln!(out, "{:<32}# {}", text, pc);
+        pc += size;
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::instruction::Dialect;
+
+    #[test]
+    fn round_trip() {
+        let registry = Registry::new(Dialect::Full);
+        let program = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99, 11101, 42,
+        ];
+        let listing = disassemble(&program, &registry);
+        assert!(listing.starts_with("arb 1                           # 0\nout [rb-1]"));
+        // 11101 has an immediate mode digit on its destination, so it stays data.
+        assert!(listing.contains("data 11101                      # 16\n"));
+        assert_eq!(assemble(&listing, &registry).unwrap(), program);
+    }
+}
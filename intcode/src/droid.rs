@@ -0,0 +1,266 @@
+use crate::grid::{bounds, Direction, Position};
+use crate::machine::Machine;
+use crate::IntCode;
+use common::{error, Res};
+use std::collections::{HashMap, VecDeque};
+
+/*
+ * The repair droid.
+ *
+ * The program reads movement commands (1 north, 2 south, 3 west, 4 east) and replies with a
+ * status for each: 0 if the droid hit a wall and stayed put, 1 if it moved, 2 if it moved
+ * onto the target. Exploration maps every cell reachable from the droid's starting point,
+ * which is (0, 0); north is up, so it decreases y.
+ */
+
+const DIRECTIONS: [Direction; 4] = [
+    Direction::Up,
+    Direction::Down,
+    Direction::Left,
+    Direction::Right,
+];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Cell {
+    Wall,
+    Open,
+    Target,
+}
+
+/// How to explore the maze.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Strategy {
+    /// Breadth first, keeping a copy of the machine for every cell on the frontier.
+    Snapshots,
+    /// Depth first with a single machine, stepping back after each dead end.
+    Backtracking,
+}
+
+fn command(direction: Direction) -> IntCode {
+    match direction {
+        Direction::Up => 1,
+        Direction::Down => 2,
+        Direction::Left => 3,
+        Direction::Right => 4,
+    }
+}
+
+/// Send the droid one step and return what it found there.
+fn send(machine: &mut Machine, direction: Direction) -> Res<Cell> {
+    machine.push_input(command(direction));
+    machine.run_until_input()?;
+    match machine.take_outputs()[..] {
+        [0] => Ok(Cell::Wall),
+        [1] => Ok(Cell::Open),
+        [2] => Ok(Cell::Target),
+        ref outputs => error(format!("Unexpected droid status: {:?}", outputs)),
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Maze {
+    cells: HashMap<Position, Cell>,
+}
+
+impl Maze {
+    /// Map the maze by driving the droid the program controls. The machine should be at the
+    /// start of the program, or paused waiting for a command.
+    pub fn explore(machine: &Machine, strategy: Strategy) -> Res<Maze> {
+        let mut machine = machine.clone();
+        machine.run_until_input()?;
+        let mut maze = Maze::default();
+        maze.cells.insert((0, 0), Cell::Open);
+        match strategy {
+            Strategy::Snapshots => maze.explore_snapshots(machine)?,
+            Strategy::Backtracking => maze.explore_backtracking(&mut machine, (0, 0))?,
+        }
+        Ok(maze)
+    }
+
+    fn explore_snapshots(&mut self, machine: Machine) -> Res<()> {
+        let mut frontier = VecDeque::new();
+        frontier.push_back(((0, 0), machine));
+        while let Some((position, machine)) = frontier.pop_front() {
+            for &direction in &DIRECTIONS {
+                let next = direction.step(position);
+                if self.cells.contains_key(&next) {
+                    continue;
+                }
+                let mut machine = machine.clone();
+                let cell = send(&mut machine, direction)?;
+                self.cells.insert(next, cell);
+                if cell != Cell::Wall {
+                    frontier.push_back((next, machine));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn explore_backtracking(&mut self, machine: &mut Machine, position: Position) -> Res<()> {
+        for &direction in &DIRECTIONS {
+            let next = direction.step(position);
+            if self.cells.contains_key(&next) {
+                continue;
+            }
+            let cell = send(machine, direction)?;
+            self.cells.insert(next, cell);
+            if cell != Cell::Wall {
+                self.explore_backtracking(machine, next)?;
+                if send(machine, direction.reverse())? == Cell::Wall {
+                    return error(format!("Droid couldn't step back to {:?}", position));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// What's known about the cell; `None` if it was never reached.
+    pub fn cell(&self, position: Position) -> Option<Cell> {
+        self.cells.get(&position).cloned()
+    }
+
+    pub fn target(&self) -> Option<Position> {
+        self.cells
+            .iter()
+            .find(|&(_, &cell)| cell == Cell::Target)
+            .map(|(&position, _)| position)
+    }
+
+    /// Distance to every open cell reachable from `from`.
+    pub fn distances(&self, from: Position) -> HashMap<Position, usize> {
+        let mut distances = HashMap::new();
+        if self.cell(from).is_none_or(|cell| cell == Cell::Wall) {
+            return distances;
+        }
+        distances.insert(from, 0);
+        let mut queue = VecDeque::new();
+        queue.push_back(from);
+        while let Some(position) = queue.pop_front() {
+            let distance = distances[&position];
+            for &direction in &DIRECTIONS {
+                let next = direction.step(position);
+                let open = self.cell(next).is_some_and(|cell| cell != Cell::Wall);
+                if open && !distances.contains_key(&next) {
+                    distances.insert(next, distance + 1);
+                    queue.push_back(next);
+                }
+            }
+        }
+        distances
+    }
+
+    /// Fewest moves between the two cells, if there's a way through.
+    pub fn shortest_path(&self, from: Position, to: Position) -> Option<usize> {
+        self.distances(from).get(&to).cloned()
+    }
+
+    /// Moves needed to fill every reachable cell from `from`, spreading one cell per move in
+    /// all directions at once.
+    pub fn flood_fill(&self, from: Position) -> Option<usize> {
+        self.distances(from).values().max().cloned()
+    }
+
+    /// The map as text: `#` wall, `.` open, `T` target, `D` the droid's start and a space for
+    /// cells never reached.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        if let Some(((min_x, min_y), (max_x, max_y))) = bounds(self.cells.keys()) {
+            for y in min_y..=max_y {
+                for x in min_x..=max_x {
+                    out.push(match self.cell((x, y)) {
+                        _ if (x, y) == (0, 0) => 'D',
+                        Some(Cell::Wall) => '#',
+                        Some(Cell::Open) => '.',
+                        Some(Cell::Target) => 'T',
+                        None => ' ',
+                    });
+                }
+                out.push('\n');
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::registry::Registry;
+    use crate::Dialect;
+
+    // A droid in a 5x5 room, reading its walls from a map laid out row by row: 0 is a wall,
+    // 1 open floor and 2 the target, which are also the statuses it replies with.
+    const DROID: &str = "
+        loop:   in [cmd]
+                add [x], 0, [nx]
+                add [y], 0, [ny]
+                eq [cmd], 1, [t]
+                jf [t], south
+                add [y], -1, [ny]
+        south:  eq [cmd], 2, [t]
+                jf [t], west
+                add [y], 1, [ny]
+        west:   eq [cmd], 3, [t]
+                jf [t], east
+                add [x], -1, [nx]
+        east:   eq [cmd], 4, [t]
+                jf [t], look
+                add [x], 1, [nx]
+        look:   mul [ny], 5, [idx]
+                add [idx], [nx], [idx]
+                arb [idx]
+                add [rb+map], 0, [cell]
+                mul [idx], -1, [idx]
+                arb [idx]
+                jf [cell], reply
+                add [nx], 0, [x]
+                add [ny], 0, [y]
+        reply:  out [cell]
+                jt 1, loop
+        cmd:    data 0
+        t:      data 0
+        x:      data 1
+        y:      data 3
+        nx:     data 0
+        ny:     data 0
+        idx:    data 0
+        cell:   data 0
+        map:    data 0, 0, 0, 0, 0
+                data 0, 1, 1, 2, 0
+                data 0, 1, 0, 1, 0
+                data 0, 1, 1, 1, 0
+                data 0, 0, 0, 0, 0
+    ";
+
+    fn droid() -> Machine {
+        let registry = Registry::new(Dialect::Full);
+        let program = assemble(DROID, &registry).unwrap();
+        Machine::with_dialect(program, Dialect::Full)
+    }
+
+    #[test]
+    fn explore() -> Res<()> {
+        let machine = droid();
+        let maze = Maze::explore(&machine, Strategy::Snapshots)?;
+        // Corners are never next to an open cell, so they stay unknown.
+        assert_eq!(maze.render(), " ### \n#..T#\n#.#.#\n#D..#\n ### \n");
+        assert_eq!(maze, Maze::explore(&machine, Strategy::Backtracking)?);
+        // Exploring works on a copy.
+        assert_eq!(machine.steps(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn queries() -> Res<()> {
+        let maze = Maze::explore(&droid(), Strategy::Snapshots)?;
+        let target = maze.target().unwrap();
+        assert_eq!(target, (2, -2));
+        assert_eq!(maze.shortest_path((0, 0), target), Some(4));
+        assert_eq!(maze.shortest_path((0, 0), (1, -1)), None);
+        assert_eq!(maze.flood_fill(target), Some(4));
+        assert_eq!(maze.distances((0, 0)).len(), 8);
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+use crate::IntCode;
+
+/// A point on an unbounded grid. As in day 3, y grows downwards.
+pub type Position = (IntCode, IntCode);
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Direction {
+    pub fn turn_left(self) -> Direction {
+        match self {
+            Direction::Up => Direction::Left,
+            Direction::Left => Direction::Down,
+            Direction::Down => Direction::Right,
+            Direction::Right => Direction::Up,
+        }
+    }
+
+    pub fn turn_right(self) -> Direction {
+        self.turn_left().turn_left().turn_left()
+    }
+
+    pub fn reverse(self) -> Direction {
+        self.turn_left().turn_left()
+    }
+
+    /// The neighbouring position in this direction.
+    pub fn step(self, (x, y): Position) -> Position {
+        match self {
+            Direction::Up => (x, y - 1),
+            Direction::Down => (x, y + 1),
+            Direction::Left => (x - 1, y),
+            Direction::Right => (x + 1, y),
+        }
+    }
+}
+
+/// The smallest rectangle holding every position, as inclusive (min, max) corners.
+pub fn bounds<'a, I: IntoIterator<Item = &'a Position>>(
+    positions: I,
+) -> Option<(Position, Position)> {
+    positions
+        .into_iter()
+        .fold(None, |bounds, &(x, y)| match bounds {
+            None => Some(((x, y), (x, y))),
+            Some(((min_x, min_y), (max_x, max_y))) => {
+                Some(((min_x.min(x), min_y.min(y)), (max_x.max(x), max_y.max(y))))
+            }
+        })
+}
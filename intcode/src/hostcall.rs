@@ -0,0 +1,405 @@
+use crate::instruction::Role;
+use crate::registry::{Context, Registry};
+use crate::IntCode;
+use common::{error, Res};
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+/*
+ * Host calls: Intcode programs asking the Rust side for services.
+ *
+ * Programs make host calls with the `sys` instruction, opcode 80, which takes a call number,
+ * three arguments and a destination for the result:
+ *
+ *     sys 1, bound, 0, 0, [dst]       random number in 0..bound
+ *     sys 2, 0, 0, 0, [dst]           steps executed so far
+ *     sys 3, addr, len, 0, [dst]      log the string held one character per cell at addr
+ *     sys 4, file, addr, len, [dst]   read up to len bytes of a file into memory at addr;
+ *                                     the result is the number of bytes read, 0 at the end
+ *
+ * The opcode is only understood by registries it has been added to with `register`. What a
+ * call actually does is up to the machine's `HostCalls`, which denies everything unless
+ * told otherwise, and which also sets the longest string a program may pass.
+ */
+
+pub const SYSCALL: IntCode = 80;
+
+pub const RANDOM: IntCode = 1;
+pub const STEPS: IntCode = 2;
+pub const LOG: IntCode = 3;
+pub const READ: IntCode = 4;
+
+/// Longest string a program may pass to a host call, unless the host says otherwise.
+pub const MAX_STRING: usize = 4096;
+
+/// Services a machine offers to the program it runs. Every call is denied by default.
+pub trait HostCalls: fmt::Debug + Send {
+    fn random(&mut self, _bound: IntCode) -> Res<IntCode> {
+        deny("random")
+    }
+
+    fn steps(&mut self, _steps: usize) -> Res<IntCode> {
+        deny("steps")
+    }
+
+    fn log(&mut self, _message: &str) -> Res<()> {
+        deny("log")
+    }
+
+    /// Read up to `len` bytes from the file, continuing where the last read left off.
+    fn read(&mut self, _file: IntCode, _len: usize) -> Res<Vec<u8>> {
+        deny("read")
+    }
+
+    /// Longest string the program may pass, checked before the string is read from memory.
+    fn max_string(&self) -> usize {
+        MAX_STRING
+    }
+}
+
+fn deny<T>(call: &str) -> Res<T> {
+    error(format!("Host call denied: {}", call))
+}
+
+/// Denies every host call.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DenyAll;
+
+impl HostCalls for DenyAll {}
+
+/// A policy allowing selected host calls. Files are only readable if added with
+/// `allow_file`, and are numbered from 0 in the order they were added.
+#[derive(Debug)]
+pub struct Sandbox {
+    /// State of the random number generator, if random numbers are allowed.
+    random: Option<u64>,
+    steps: bool,
+    log: Option<Vec<String>>,
+    files: Vec<(PathBuf, u64)>,
+    /// Largest read allowed in one call.
+    max_read: usize,
+    /// Longest string allowed in one call.
+    max_string: usize,
+}
+
+impl Default for Sandbox {
+    fn default() -> Sandbox {
+        Sandbox::new()
+    }
+}
+
+impl Sandbox {
+    pub fn new() -> Sandbox {
+        Sandbox {
+            random: None,
+            steps: false,
+            log: None,
+            files: vec![],
+            max_read: 4096,
+            max_string: MAX_STRING,
+        }
+    }
+
+    /// Allow random numbers, generated deterministically from the seed.
+    pub fn allow_random(mut self, seed: u64) -> Sandbox {
+        // xorshift gets stuck at zero.
+        self.random = Some(seed.max(1));
+        self
+    }
+
+    pub fn allow_steps(mut self) -> Sandbox {
+        self.steps = true;
+        self
+    }
+
+    /// Allow logging; messages are kept for `logs`.
+    pub fn allow_log(mut self) -> Sandbox {
+        self.log = Some(vec![]);
+        self
+    }
+
+    pub fn allow_file<P: Into<PathBuf>>(mut self, path: P) -> Sandbox {
+        self.files.push((path.into(), 0));
+        self
+    }
+
+    pub fn with_max_read(mut self, max_read: usize) -> Sandbox {
+        self.max_read = max_read;
+        self
+    }
+
+    pub fn with_max_string(mut self, max_string: usize) -> Sandbox {
+        self.max_string = max_string;
+        self
+    }
+
+    pub fn logs(&self) -> &[String] {
+        self.log.as_deref().unwrap_or(&[])
+    }
+}
+
+impl HostCalls for Sandbox {
+    fn random(&mut self, bound: IntCode) -> Res<IntCode> {
+        let state = match &mut self.random {
+            Some(state) => state,
+            None => return deny("random"),
+        };
+        if bound <= 0 {
+            return error(format!("Invalid random bound: {}", bound));
+        }
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        Ok((*state % bound as u64) as IntCode)
+    }
+
+    fn steps(&mut self, steps: usize) -> Res<IntCode> {
+        if !self.steps {
+            return deny("steps");
+        }
+        Ok(steps as IntCode)
+    }
+
+    fn log(&mut self, message: &str) -> Res<()> {
+        match &mut self.log {
+            Some(log) => {
+                log.push(message.to_string());
+                Ok(())
+            }
+            None => deny("log"),
+        }
+    }
+
+    fn read(&mut self, file: IntCode, len: usize) -> Res<Vec<u8>> {
+        if len > self.max_read {
+            return error(format!(
+                "Read of {} bytes exceeds limit {}",
+                len, self.max_read
+            ));
+        }
+        let (path, offset) = match usize::try_from(file)
+            .ok()
+            .and_then(|i| self.files.get_mut(i))
+        {
+            Some(entry) => entry,
+            None => return deny("read"),
+        };
+        let mut handle = File::open(path)?;
+        handle.seek(SeekFrom::Start(*offset))?;
+        let mut bytes = vec![];
+        handle.take(len as u64).read_to_end(&mut bytes)?;
+        *offset += bytes.len() as u64;
+        Ok(bytes)
+    }
+
+    fn max_string(&self) -> usize {
+        self.max_string
+    }
+}
+
+/// Add the `sys` instruction to the registry.
+pub fn register(registry: &mut Registry) -> Res<()> {
+    registry.register(
+        SYSCALL,
+        "sys",
+        &[Role::Read, Role::Read, Role::Read, Role::Read, Role::Write],
+        syscall,
+    )
+}
+
+fn syscall(context: &mut Context) -> Res<()> {
+    let call = context.arg(0)?;
+    let (a, b, c) = (context.arg(1)?, context.arg(2)?, context.arg(3)?);
+    let result = match call {
+        RANDOM => context.host()?.random(a)?,
+        STEPS => {
+            let steps = context.machine().steps();
+            context.host()?.steps(steps)?
+        }
+        LOG => {
+            let max = context.host()?.max_string();
+            let message = read_string(context, a, b, max)?;
+            context.host()?.log(&message)?;
+            0
+        }
+        READ => {
+            let len = match usize::try_from(c) {
+                Ok(len) => len,
+                Err(_) => return error(format!("Invalid read length: {}", c)),
+            };
+            let bytes = context.host()?.read(a, len)?;
+            for (index, &byte) in bytes.iter().enumerate() {
+                let addr = match b.checked_add(index as IntCode) {
+                    Some(addr) => addr,
+                    None => return error(format!("Invalid address: {}+{}", b, index)),
+                };
+                context.write(addr, byte as IntCode)?;
+            }
+            bytes.len() as IntCode
+        }
+        _ => return error(format!("Unknown host call {} at {}", call, context.pc())),
+    };
+    context.store(4, result)
+}
+
+fn read_string(context: &Context, addr: IntCode, len: IntCode, max: usize) -> Res<String> {
+    match usize::try_from(len) {
+        Ok(len) if len <= max => {}
+        Ok(_) => {
+            return error(format!(
+                "String of {} characters exceeds limit {}",
+                len, max
+            ))
+        }
+        Err(_) => return error(format!("Invalid string length: {}", len)),
+    }
+    let mut message = String::new();
+    for offset in 0..len {
+        let value = match addr.checked_add(offset) {
+            Some(addr) => context.peek(addr)?,
+            None => return error(format!("Invalid address: {}+{}", addr, offset)),
+        };
+        match u32::try_from(value).ok().and_then(char::from_u32) {
+            Some(c) => message.push(c),
+            None => return error(format!("Not a character: {}", value)),
+        }
+    }
+    Ok(message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::instruction::Dialect;
+    use crate::limits::DEFAULT_MAX_MEMORY;
+    use crate::machine::Machine;
+    use std::sync::{Arc, Mutex};
+
+    fn machine(source: &str) -> Machine {
+        let mut registry = Registry::new(Dialect::Day05);
+        register(&mut registry).unwrap();
+        let program = assemble(source, &registry).unwrap();
+        Machine::with_registry(program, Arc::new(registry))
+    }
+
+    /// A machine for the full dialect, with host calls and a sandbox allowing logs.
+    fn full(program: Vec<IntCode>) -> Machine {
+        let mut registry = Registry::new(Dialect::Full);
+        register(&mut registry).unwrap();
+        let mut machine = Machine::with_registry(program, Arc::new(registry));
+        machine.set_host(Arc::new(Mutex::new(Sandbox::new().allow_log())));
+        machine
+    }
+
+    const HELLO: &str = "
+            sys 3, msg, 2, 0, [result]
+            sys 2, 0, 0, 0, [result]
+            out [result]
+            hlt
+        result: data 0
+        msg:    data 104, 105
+    ";
+
+    #[test]
+    fn deny_by_default() {
+        let error = machine(HELLO).run().unwrap_err();
+        assert_eq!(error.to_string(), "Host call denied: log");
+    }
+
+    #[test]
+    fn sandbox() -> Res<()> {
+        // Defaults match `new`, rather than denying every string.
+        let mut defaults = machine(HELLO);
+        defaults.set_host(Arc::new(Mutex::new(
+            Sandbox::default().allow_log().allow_steps(),
+        )));
+        defaults.run()?;
+        assert_eq!(defaults.outputs(), &[1]);
+
+        let sandbox = Arc::new(Mutex::new(Sandbox::new().allow_log().allow_steps()));
+        let mut machine = machine(HELLO);
+        machine.set_host(sandbox.clone());
+        machine.run()?;
+        assert_eq!(machine.outputs(), &[1]);
+        assert_eq!(sandbox.lock().unwrap().logs(), &["hi".to_string()]);
+
+        let mut machine = self::machine("sys 1, 6, 0, 0, [9]\nout [9]\nhlt\ndata 0");
+        machine.set_host(Arc::new(Mutex::new(Sandbox::new().allow_random(42))));
+        machine.run()?;
+        assert!((0..6).contains(&machine.outputs()[0]));
+        Ok(())
+    }
+
+    #[test]
+    fn strings() {
+        let error = full(vec![111180, 3, IntCode::MAX, 2, 0, 0, 99])
+            .run()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("Invalid address: {}+1", IntCode::MAX)
+        );
+        let error = full(vec![111180, 3, 0, 1 << 40, 0, 0, 99])
+            .run()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "String of 1099511627776 characters exceeds limit 4096"
+        );
+        assert!(full(vec![111180, 3, 0, -1, 0, 0, 99]).run().is_err());
+
+        let mut machine = machine(HELLO);
+        machine.set_host(Arc::new(Mutex::new(
+            Sandbox::new().allow_log().with_max_string(1),
+        )));
+        let error = machine.run().unwrap_err();
+        assert_eq!(error.to_string(), "String of 2 characters exceeds limit 1");
+    }
+
+    #[test]
+    fn read_files() -> Res<()> {
+        let path =
+            std::env::temp_dir().join(format!("intcode_hostcall_read_{}.txt", std::process::id()));
+        std::fs::write(&path, "abc")?;
+        let source = "
+            loop:   sys 4, 0, buf, 2, [n]
+                    jf [n], end
+                    out [buf]
+                    jt 1, loop
+            end:    sys 4, 1, buf, 1, [n]
+                    hlt
+            n:      data 0
+            buf:    data 0, 0
+        ";
+        let mut machine = machine(source);
+        machine.set_host(Arc::new(Mutex::new(Sandbox::new().allow_file(&path))));
+        let error = machine.run().unwrap_err();
+        // Only file 0 was allowed.
+        assert_eq!(error.to_string(), "Host call denied: read");
+        assert_eq!(machine.outputs(), &[97, 99]);
+
+        let sandbox = Sandbox::new().allow_file(&path).with_max_read(1);
+        let mut machine = self::machine(source);
+        machine.set_host(Arc::new(Mutex::new(sandbox)));
+        assert!(machine.run().is_err());
+
+        // A buffer at the very top of the address space faults rather than wrapping around.
+        let mut machine = full(vec![111180, 4, 0, IntCode::MAX, 2, 0, 99]);
+        machine.set_host(Arc::new(Mutex::new(Sandbox::new().allow_file(&path))));
+        let error = machine.run().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Address {} beyond memory limit {} at 0",
+                IntCode::MAX,
+                DEFAULT_MAX_MEMORY
+            )
+        );
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+}
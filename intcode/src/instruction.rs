@@ -0,0 +1,185 @@
+use crate::IntCode;
+use common::{error, Res};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// The instruction set a program was written against.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Dialect {
+    /// Day 2: add, multiply and halt, with position parameters only.
+    Day02,
+    /// Day 5: adds I/O, jumps, comparisons and immediate parameters.
+    #[default]
+    Day05,
+    /// Adds relative parameters, the relative base and memory beyond the program.
+    Full,
+}
+
+impl Dialect {
+    pub fn name(self) -> &'static str {
+        match self {
+            Dialect::Day02 => "day02",
+            Dialect::Day05 => "day05",
+            Dialect::Full => "full",
+        }
+    }
+
+    /// Whether the dialect includes the given built-in opcode.
+    pub fn supports(self, opcode: Opcode) -> bool {
+        match opcode {
+            Opcode::Add | Opcode::Multiply | Opcode::Halt => true,
+            Opcode::AdjustRelativeBase => self == Dialect::Full,
+            Opcode::Custom(_) => false,
+            _ => self != Dialect::Day02,
+        }
+    }
+
+    pub fn supports_mode(self, mode: ParameterMode) -> bool {
+        match mode {
+            ParameterMode::Position => true,
+            ParameterMode::Immediate => self != Dialect::Day02,
+            ParameterMode::Relative => self == Dialect::Full,
+        }
+    }
+
+    /// Whether addresses past the end of the program are valid, reading as 0 until written.
+    pub fn grows_memory(self) -> bool {
+        self == Dialect::Full
+    }
+}
+
+impl FromStr for Dialect {
+    type Err = Box<dyn Error>;
+
+    fn from_str(name: &str) -> Res<Dialect> {
+        match name {
+            "day02" => Ok(Dialect::Day02),
+            "day05" => Ok(Dialect::Day05),
+            "full" => Ok(Dialect::Full),
+            _ => error(format!("Unknown dialect: {}", name)),
+        }
+    }
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Operations in the full instruction set. Not every dialect supports all of them, and
+/// anything else is registered by the user.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Opcode {
+    Add,
+    Multiply,
+    Input,
+    Output,
+    JumpIfTrue,
+    JumpIfFalse,
+    LessThan,
+    Equals,
+    AdjustRelativeBase,
+    Halt,
+    Custom(IntCode),
+}
+
+impl Opcode {
+    /// The opcode's value in memory, without parameter modes.
+    pub fn code(self) -> IntCode {
+        match self {
+            Opcode::Add => 1,
+            Opcode::Multiply => 2,
+            Opcode::Input => 3,
+            Opcode::Output => 4,
+            Opcode::JumpIfTrue => 5,
+            Opcode::JumpIfFalse => 6,
+            Opcode::LessThan => 7,
+            Opcode::Equals => 8,
+            Opcode::AdjustRelativeBase => 9,
+            Opcode::Halt => 99,
+            Opcode::Custom(code) => code,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ParameterMode {
+    Position,
+    Immediate,
+    Relative,
+}
+
+impl ParameterMode {
+    pub fn from_int(value: IntCode) -> Res<ParameterMode> {
+        match value {
+            0 => Ok(ParameterMode::Position),
+            1 => Ok(ParameterMode::Immediate),
+            2 => Ok(ParameterMode::Relative),
+            _ => error("Unsupported parameter mode."),
+        }
+    }
+
+    /// The mode's digit in an instruction.
+    pub fn digit(self) -> IntCode {
+        match self {
+            ParameterMode::Position => 0,
+            ParameterMode::Immediate => 1,
+            ParameterMode::Relative => 2,
+        }
+    }
+}
+
+/// Whether an instruction reads a parameter's value or writes to the address it names.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Role {
+    Read,
+    Write,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Parameter {
+    pub mode: ParameterMode,
+    pub role: Role,
+    pub value: IntCode,
+}
+
+/// A single decoded instruction.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Instruction {
+    pub opcode: Opcode,
+    pub mnemonic: &'static str,
+    pub params: Vec<Parameter>,
+}
+
+impl Instruction {
+    /// Number of memory cells taken up by the instruction.
+    pub fn size(&self) -> usize {
+        1 + self.params.len()
+    }
+
+    /// The parameter holding the destination address, if the instruction writes to memory.
+    pub fn destination(&self) -> Option<Parameter> {
+        self.params
+            .iter()
+            .find(|param| param.role == Role::Write)
+            .cloned()
+    }
+}
+
+/// Disassembly, e.g. `add [9], 10, [3]`. Position parameters are shown in brackets.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.mnemonic)?;
+        for (index, param) in self.params.iter().enumerate() {
+            let separator = if index == 0 { " " } else { ", " };
+            match param.mode {
+                ParameterMode::Position => write!(f, "{}[{}]", separator, param.value)?,
+                ParameterMode::Immediate => write!(f, "{}{}", separator, param.value)?,
+                ParameterMode::Relative => write!(f, "{}[rb{:+}]", separator, param.value)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,25 @@
+pub mod arcade;
+pub mod asm;
+pub mod coverage;
+pub mod device;
+pub mod diff;
+pub mod disasm;
+pub mod droid;
+pub mod grid;
+pub mod hostcall;
+pub mod instruction;
+pub mod limits;
+pub mod loader;
+pub mod machine;
+pub mod optimize;
+pub mod paint;
+pub mod patch;
+pub mod probe;
+pub mod registry;
+pub mod session;
+
+pub use instruction::{Dialect, Instruction, Opcode, ParameterMode, Role};
+pub use machine::Machine;
+pub use registry::Registry;
+
+pub type IntCode = i64;
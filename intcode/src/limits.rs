@@ -0,0 +1,82 @@
+use crate::IntCode;
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+/// Most cells memory may grow to when `Limits::max_memory` isn't set, so that a write to a
+/// huge address faults rather than exhausting the host's memory.
+pub const DEFAULT_MAX_MEMORY: usize = 1 << 24;
+
+/// Restrictions for running untrusted programs. Nothing is restricted by default, except that
+/// memory never grows past `DEFAULT_MAX_MEMORY` cells unless another limit is given.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Limits {
+    /// Most cells memory may grow to, in dialects that grow memory. Defaults to
+    /// `DEFAULT_MAX_MEMORY`.
+    pub max_memory: Option<usize>,
+    /// Addresses that trap when written, typically the program's code.
+    pub read_only: Option<Range<usize>>,
+    pub max_outputs: Option<usize>,
+}
+
+impl Limits {
+    pub fn with_max_memory(mut self, cells: usize) -> Limits {
+        self.max_memory = Some(cells);
+        self
+    }
+
+    pub fn with_read_only(mut self, addrs: Range<usize>) -> Limits {
+        self.read_only = Some(addrs);
+        self
+    }
+
+    pub fn with_max_outputs(mut self, outputs: usize) -> Limits {
+        self.max_outputs = Some(outputs);
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FaultKind {
+    NegativeAddress(IntCode),
+    /// A relative parameter whose address doesn't fit in an `IntCode`.
+    AddressOverflow {
+        base: IntCode,
+        offset: IntCode,
+    },
+    /// Outside memory, in a dialect that doesn't grow it.
+    OutOfBounds(IntCode),
+    MemoryLimit {
+        addr: IntCode,
+        limit: usize,
+    },
+    ReadOnly(usize),
+    OutputLimit(usize),
+}
+
+/// A memory access or limit violation, and the pc of the instruction that caused it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Fault {
+    pub pc: usize,
+    pub kind: FaultKind,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            FaultKind::NegativeAddress(addr) => write!(f, "Negative address {}", addr)?,
+            FaultKind::AddressOverflow { base, offset } => {
+                write!(f, "Address {}{:+} overflows", base, offset)?
+            }
+            FaultKind::OutOfBounds(addr) => write!(f, "Address {} out of bounds", addr)?,
+            FaultKind::MemoryLimit { addr, limit } => {
+                write!(f, "Address {} beyond memory limit {}", addr, limit)?
+            }
+            FaultKind::ReadOnly(addr) => write!(f, "Write to read-only address {}", addr)?,
+            FaultKind::OutputLimit(limit) => write!(f, "More than {} outputs", limit)?,
+        }
+        write!(f, " at {}", self.pc)
+    }
+}
+
+impl Error for Fault {}
@@ -0,0 +1,380 @@
+use crate::instruction::Dialect;
+use crate::machine::Machine;
+use crate::registry::Registry;
+use crate::IntCode;
+use common::Res;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::sync::Arc;
+
+/*
+ * Intcode program files.
+ *
+ * Text programs are comma-separated integers. Whitespace and newlines may appear anywhere
+ * between values, `#` starts a comment running to the end of the line, and an optional
+ * header of `@key value` lines may come before the first value:
+ *
+ *     @name diagnostic
+ *     @dialect day05
+ *     @entry 0
+ *     3,225,1,225,6,6,1100,   # read the system id
+ *     ...
+ *
+ * Binary programs start with the magic bytes `ICB1`, followed by the number of header
+ * entries, each as a length-prefixed key and value, then the number of cells and the cells
+ * themselves. Counts and lengths are LEB128 varints; cells are zigzag-encoded varints.
+ */
+
+const MAGIC: &[u8] = b"ICB1";
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Metadata {
+    pub name: Option<String>,
+    pub dialect: Option<Dialect>,
+    /// Address execution starts from.
+    pub entry: usize,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Program {
+    pub metadata: Metadata,
+    pub code: Vec<IntCode>,
+}
+
+/// Why a program failed to load. Lines and columns count from 1.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LoadError {
+    Text {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    Binary {
+        offset: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Text {
+                line,
+                column,
+                message,
+            } => write!(f, "line {}, column {}: {}", line, column, message),
+            LoadError::Binary { offset, message } => write!(f, "byte {}: {}", offset, message),
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+/// Load a text or binary program from a file.
+pub fn load(filename: &str) -> Res<Program> {
+    let bytes = fs::read(filename)?;
+    if bytes.starts_with(MAGIC) {
+        Ok(Program::from_binary(&bytes)?)
+    } else {
+        Ok(Program::parse(&String::from_utf8(bytes)?)?)
+    }
+}
+
+impl Program {
+    pub fn new(code: Vec<IntCode>) -> Program {
+        Program {
+            metadata: Metadata::default(),
+            code,
+        }
+    }
+
+    /// A machine loaded with the program, ready to start at its entry point. Programs that
+    /// don't name a dialect run as day 5 programs.
+    pub fn machine(&self) -> Machine {
+        let dialect = self.metadata.dialect.unwrap_or_default();
+        self.machine_with_registry(Arc::new(Registry::new(dialect)))
+    }
+
+    /// Like `machine`, but running whatever instruction set the registry describes.
+    pub fn machine_with_registry(&self, registry: Arc<Registry>) -> Machine {
+        let mut machine = Machine::with_registry(self.code.clone(), registry);
+        machine.pc = self.metadata.entry;
+        machine
+    }
+
+    pub fn parse(contents: &str) -> Result<Program, LoadError> {
+        let mut program = Program::default();
+        // Whether a value is allowed next, i.e. we've seen a comma since the last value.
+        let mut expecting_value = true;
+        for (line_index, line) in contents.lines().enumerate() {
+            let line_number = line_index + 1;
+            let syntax_error = |column: usize, message: String| LoadError::Text {
+                line: line_number,
+                column: column + 1,
+                message,
+            };
+
+            let code = match line.find('#') {
+                Some(index) => &line[..index],
+                None => line,
+            };
+            if code.trim_start().starts_with('@') {
+                if !program.code.is_empty() {
+                    let column = code.find('@').unwrap();
+                    return Err(syntax_error(column, "header after program".to_string()));
+                }
+                program
+                    .parse_header(code.trim())
+                    .map_err(|message| syntax_error(code.find('@').unwrap(), message))?;
+                continue;
+            }
+
+            let mut chars = code.char_indices().peekable();
+            while let Some(&(column, c)) = chars.peek() {
+                if c.is_whitespace() {
+                    chars.next();
+                } else if c == ',' {
+                    if expecting_value {
+                        return Err(syntax_error(column, "empty value".to_string()));
+                    }
+                    expecting_value = true;
+                    chars.next();
+                } else {
+                    let mut end = column;
+                    while let Some(&(index, c)) = chars.peek() {
+                        if c.is_whitespace() || c == ',' {
+                            break;
+                        }
+                        end = index + c.len_utf8();
+                        chars.next();
+                    }
+                    let token = &code[column..end];
+                    if !expecting_value {
+                        return Err(syntax_error(
+                            column,
+                            format!("missing comma before \"{}\"", token),
+                        ));
+                    }
+                    match token.parse::<IntCode>() {
+                        Ok(value) => program.code.push(value),
+                        Err(_) => {
+                            return Err(syntax_error(
+                                column,
+                                format!("invalid integer \"{}\"", token),
+                            ))
+                        }
+                    }
+                    expecting_value = false;
+                }
+            }
+        }
+        Ok(program)
+    }
+
+    fn parse_header(&mut self, line: &str) -> Result<(), String> {
+        let mut parts = line[1..].splitn(2, char::is_whitespace);
+        let key = parts.next().unwrap_or("");
+        let value = parts.next().unwrap_or("").trim().to_string();
+        if value.is_empty() {
+            return Err(format!("missing value for @{}", key));
+        }
+        match key {
+            "name" => self.metadata.name = Some(value),
+            "dialect" => self.metadata.dialect = Some(value.parse().map_err(|e| format!("{}", e))?),
+            "entry" => {
+                self.metadata.entry = value
+                    .parse()
+                    .map_err(|_| format!("invalid entry point \"{}\"", value))?
+            }
+            _ => return Err(format!("unknown header \"@{}\"", key)),
+        }
+        Ok(())
+    }
+
+    pub fn from_binary(bytes: &[u8]) -> Result<Program, LoadError> {
+        let mut reader = Reader { bytes, offset: 0 };
+        if !bytes.starts_with(MAGIC) {
+            return Err(reader.error("not a binary Intcode program"));
+        }
+        reader.offset = MAGIC.len();
+
+        let mut program = Program::default();
+        for _ in 0..reader.varint()? {
+            let key = reader.string()?;
+            let value = reader.string()?;
+            program
+                .parse_header(&format!("@{} {}", key, value))
+                .map_err(|message| reader.error(&message))?;
+        }
+        let len = reader.varint()? as usize;
+        // Don't trust the length for the allocation; each cell takes at least a byte.
+        program.code.reserve(len.min(bytes.len()));
+        for _ in 0..len {
+            let value = reader.varint()?;
+            program
+                .code
+                .push(((value >> 1) as IntCode) ^ -((value & 1) as IntCode));
+        }
+        if reader.offset != bytes.len() {
+            return Err(reader.error("trailing bytes"));
+        }
+        Ok(program)
+    }
+
+    pub fn to_binary(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        let mut header = vec![];
+        if let Some(name) = &self.metadata.name {
+            header.push(("name", name.clone()));
+        }
+        if let Some(dialect) = self.metadata.dialect {
+            header.push(("dialect", dialect.name().to_string()));
+        }
+        if self.metadata.entry != 0 {
+            header.push(("entry", self.metadata.entry.to_string()));
+        }
+        write_varint(&mut bytes, header.len() as u64);
+        for (key, value) in header {
+            write_varint(&mut bytes, key.len() as u64);
+            bytes.extend(key.as_bytes());
+            write_varint(&mut bytes, value.len() as u64);
+            bytes.extend(value.as_bytes());
+        }
+        write_varint(&mut bytes, self.code.len() as u64);
+        for &value in &self.code {
+            write_varint(&mut bytes, ((value << 1) ^ (value >> 63)) as u64);
+        }
+        bytes
+    }
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn error(&self, message: &str) -> LoadError {
+        LoadError::Binary {
+            offset: self.offset,
+            message: message.to_string(),
+        }
+    }
+
+    fn varint(&mut self) -> Result<u64, LoadError> {
+        let mut value: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = match self.bytes.get(self.offset) {
+                Some(&byte) => byte,
+                None => return Err(self.error("unexpected end of file")),
+            };
+            self.offset += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(self.error("varint too long"))
+    }
+
+    fn string(&mut self) -> Result<String, LoadError> {
+        let len = self.varint()? as usize;
+        let end = self.offset.saturating_add(len);
+        if end > self.bytes.len() {
+            return Err(self.error("unexpected end of file"));
+        }
+        let value = String::from_utf8(self.bytes[self.offset..end].to_vec())
+            .map_err(|_| self.error("invalid UTF-8"))?;
+        self.offset = end;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn plain_text() -> Result<(), LoadError> {
+        assert_eq!(Program::parse("1,0,0,0,99\n")?.code, vec![1, 0, 0, 0, 99]);
+        assert_eq!(Program::parse("")?.code, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn whitespace_comments_and_header() -> Result<(), LoadError> {
+        let program = Program::parse(
+            "# Example program\n@name example\n@dialect day05\n@entry 4\n\n1, 0, 0, 0,  # add\r\n  99,\n",
+        )?;
+        assert_eq!(program.code, vec![1, 0, 0, 0, 99]);
+        assert_eq!(program.metadata.name, Some("example".to_string()));
+        assert_eq!(program.metadata.dialect, Some(Dialect::Day05));
+        assert_eq!(program.metadata.entry, 4);
+        assert_eq!(program.machine().pc(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn text_errors() {
+        let error = |contents| Program::parse(contents).unwrap_err().to_string();
+        assert_eq!(
+            error("1,2,\n3,x4"),
+            "line 2, column 3: invalid integer \"x4\""
+        );
+        assert_eq!(error("1,,2"), "line 1, column 3: empty value");
+        assert_eq!(error("1 2"), "line 1, column 3: missing comma before \"2\"");
+        assert_eq!(
+            error("1\n  @name late"),
+            "line 2, column 3: header after program"
+        );
+        assert_eq!(
+            error("@colour red"),
+            "line 1, column 1: unknown header \"@colour\""
+        );
+        assert_eq!(
+            error("@entry -1"),
+            "line 1, column 1: invalid entry point \"-1\""
+        );
+    }
+
+    #[test]
+    fn binary_round_trip() -> Result<(), LoadError> {
+        let mut program = Program::new(vec![1, -1, 300, IntCode::MIN, IntCode::MAX, 99]);
+        program.metadata.name = Some("round trip".to_string());
+        program.metadata.dialect = Some(Dialect::Full);
+        program.metadata.entry = 2;
+        let bytes = program.to_binary();
+        assert!(bytes.starts_with(MAGIC));
+        assert_eq!(Program::from_binary(&bytes)?, program);
+        Ok(())
+    }
+
+    #[test]
+    fn binary_errors() {
+        let bytes = Program::new(vec![1, 2, 3]).to_binary();
+        assert_eq!(
+            Program::from_binary(&bytes[..bytes.len() - 1]).unwrap_err(),
+            LoadError::Binary {
+                offset: 8,
+                message: "unexpected end of file".to_string()
+            }
+        );
+        assert!(Program::from_binary(b"ICB2").is_err());
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(Program::from_binary(&trailing).is_err());
+    }
+}
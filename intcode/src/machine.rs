@@ -0,0 +1,430 @@
+use crate::hostcall::{DenyAll, HostCalls};
+use crate::instruction::{Dialect, Instruction, Opcode, Parameter, ParameterMode};
+use crate::limits::{Fault, FaultKind, Limits, DEFAULT_MAX_MEMORY};
+use crate::registry::{Context, Registry};
+use crate::IntCode;
+use common::{error, Res};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// A memory write performed by a single instruction.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Write {
+    pub addr: usize,
+    pub old: IntCode,
+    pub new: IntCode,
+}
+
+/// Everything observable about one executed instruction.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Step {
+    pub pc: usize,
+    pub instruction: Instruction,
+    pub write: Option<Write>,
+    /// For conditional jumps, whether the jump was taken, and for comparisons, whether the
+    /// comparison held.
+    pub branch: Option<bool>,
+    pub input: Option<IntCode>,
+    pub output: Option<IntCode>,
+}
+
+/// An Intcode interpreter. Machines run the day 5 dialect unless told otherwise.
+#[derive(Clone, Debug)]
+pub struct Machine {
+    pub(crate) registry: Arc<Registry>,
+    /// Shared between clones of the machine.
+    pub(crate) host: Arc<Mutex<dyn HostCalls>>,
+    pub(crate) memory: Vec<IntCode>,
+    pub(crate) pc: usize,
+    pub(crate) relative_base: IntCode,
+    pub(crate) inputs: VecDeque<IntCode>,
+    pub(crate) outputs: Vec<IntCode>,
+    /// Outputs produced so far, including any already taken.
+    pub(crate) output_count: usize,
+    pub(crate) limits: Limits,
+    pub(crate) steps: usize,
+    pub(crate) halted: bool,
+}
+
+impl Machine {
+    pub fn new(memory: Vec<IntCode>) -> Machine {
+        Machine::with_dialect(memory, Dialect::default())
+    }
+
+    pub fn with_dialect(memory: Vec<IntCode>, dialect: Dialect) -> Machine {
+        Machine::with_registry(memory, Arc::new(Registry::new(dialect)))
+    }
+
+    /// A machine running whatever instruction set the registry describes.
+    pub fn with_registry(memory: Vec<IntCode>, registry: Arc<Registry>) -> Machine {
+        Machine {
+            registry,
+            host: Arc::new(Mutex::new(DenyAll)),
+            memory,
+            pc: 0,
+            relative_base: 0,
+            inputs: VecDeque::new(),
+            outputs: vec![],
+            output_count: 0,
+            limits: Limits::default(),
+            steps: 0,
+            halted: false,
+        }
+    }
+
+    pub fn with_inputs(memory: Vec<IntCode>, inputs: Vec<IntCode>) -> Machine {
+        let mut machine = Machine::new(memory);
+        machine.inputs.extend(inputs);
+        machine
+    }
+
+    /// Provide host calls to the program; by default every call is denied.
+    pub fn set_host(&mut self, host: Arc<Mutex<dyn HostCalls>>) {
+        self.host = host;
+    }
+
+    /// Restrict what the program may do; violations stop it with a `Fault`.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn push_input(&mut self, value: IntCode) {
+        self.inputs.push_back(value);
+    }
+
+    pub fn dialect(&self) -> Dialect {
+        self.registry.dialect()
+    }
+
+    pub fn registry(&self) -> &Arc<Registry> {
+        &self.registry
+    }
+
+    pub fn memory(&self) -> &[IntCode] {
+        &self.memory
+    }
+
+    pub fn into_memory(self) -> Vec<IntCode> {
+        self.memory
+    }
+
+    pub fn outputs(&self) -> &[IntCode] {
+        &self.outputs
+    }
+
+    pub fn take_outputs(&mut self) -> Vec<IntCode> {
+        std::mem::take(&mut self.outputs)
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Number of instructions executed so far.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// True once the program has executed a halt or run off the end of memory.
+    pub fn is_halted(&self) -> bool {
+        self.halted || self.pc >= self.memory.len()
+    }
+
+    /// True if the next instruction reads input and none is queued.
+    pub fn needs_input(&self) -> bool {
+        self.inputs.is_empty() && !self.is_halted() && self.memory[self.pc] % 100 == 3
+    }
+
+    /// Run until the program halts.
+    pub fn run(&mut self) -> Res<()> {
+        while !self.is_halted() {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    /// Run until the program halts, passing every executed step to `observe`.
+    pub fn run_with<F: FnMut(&Step)>(&mut self, mut observe: F) -> Res<()> {
+        while !self.is_halted() {
+            observe(&self.step()?);
+        }
+        Ok(())
+    }
+
+    /// Run until the program halts or wants input that isn't queued. Call again once input
+    /// has been pushed to carry on.
+    pub fn run_until_input(&mut self) -> Res<()> {
+        while !self.is_halted() && !self.needs_input() {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    /// Execute a single instruction.
+    pub fn step(&mut self) -> Res<Step> {
+        let pc = self.pc;
+        let instruction = self.registry.decode(&self.memory, pc)?;
+        let handler = match self.registry.spec(instruction.opcode.code()) {
+            Some(spec) => spec.handler,
+            None => return error(format!("Invalid opcode: {}", self.memory[pc])),
+        };
+        let mut context = Context::new(self, pc, instruction);
+        handler(&mut context)?;
+        let (step, next_pc) = context.finish();
+
+        self.pc = next_pc;
+        self.steps += 1;
+        Ok(step)
+    }
+
+    pub(crate) fn read(&self, param: Parameter) -> Res<IntCode> {
+        if param.mode == ParameterMode::Immediate {
+            return Ok(param.value);
+        }
+        let addr = self.resolve(param)?;
+        if addr < 0 {
+            return self.fault(FaultKind::NegativeAddress(addr));
+        }
+        match self.memory.get(addr as usize) {
+            Some(&value) => Ok(value),
+            None if self.dialect().grows_memory() => Ok(0),
+            None => self.fault(FaultKind::OutOfBounds(addr)),
+        }
+    }
+
+    pub(crate) fn write(&mut self, addr: IntCode, value: IntCode) -> Res<Write> {
+        if addr < 0 {
+            return self.fault(FaultKind::NegativeAddress(addr));
+        }
+        if addr as usize >= self.memory.len() && !self.dialect().grows_memory() {
+            return self.fault(FaultKind::OutOfBounds(addr));
+        }
+        let addr = addr as usize;
+        if self
+            .limits
+            .read_only
+            .as_ref()
+            .is_some_and(|range| range.contains(&addr))
+        {
+            return self.fault(FaultKind::ReadOnly(addr));
+        }
+        if addr >= self.memory.len() {
+            let limit = self.limits.max_memory.unwrap_or(DEFAULT_MAX_MEMORY);
+            if addr >= limit {
+                return self.fault(FaultKind::MemoryLimit {
+                    addr: addr as IntCode,
+                    limit,
+                });
+            }
+            self.memory.resize(addr + 1, 0);
+        }
+        let old = self.memory[addr];
+        self.memory[addr] = value;
+        Ok(Write {
+            addr,
+            old,
+            new: value,
+        })
+    }
+
+    pub(crate) fn push_output(&mut self, value: IntCode) -> Res<()> {
+        if let Some(limit) = self.limits.max_outputs {
+            if self.output_count >= limit {
+                return self.fault(FaultKind::OutputLimit(limit));
+            }
+        }
+        self.output_count += 1;
+        self.outputs.push(value);
+        Ok(())
+    }
+
+    /// A fault at the instruction being executed.
+    fn fault<T>(&self, kind: FaultKind) -> Res<T> {
+        Err(Box::new(Fault { pc: self.pc, kind }))
+    }
+
+    /// The address a position or relative parameter refers to.
+    pub(crate) fn resolve(&self, param: Parameter) -> Res<IntCode> {
+        match param.mode {
+            ParameterMode::Relative => match self.relative_base.checked_add(param.value) {
+                Some(addr) => Ok(addr),
+                None => self.fault(FaultKind::AddressOverflow {
+                    base: self.relative_base,
+                    offset: param.value,
+                }),
+            },
+            _ => Ok(param.value),
+        }
+    }
+}
+
+/// Evaluate one of the two-operand instructions.
+pub(crate) fn alu(opcode: Opcode, src0: IntCode, src1: IntCode, pc: usize) -> Res<IntCode> {
+    let value = match opcode {
+        Opcode::Add => src0.checked_add(src1),
+        Opcode::Multiply => src0.checked_mul(src1),
+        Opcode::LessThan => Some(if src0 < src1 { 1 } else { 0 }),
+        Opcode::Equals => Some(if src0 == src1 { 1 } else { 0 }),
+        _ => return error(format!("Not an arithmetic opcode: {:?}", opcode)),
+    };
+    match value {
+        Some(value) => Ok(value),
+        None => error(format!("Arithmetic overflow at {}", pc)),
+    }
+}
+
+pub(crate) fn jump_target(target: IntCode, pc: usize) -> Res<usize> {
+    if target < 0 {
+        return error(format!("Invalid jump target {} at {}", target, pc));
+    }
+    Ok(target as usize)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn step_reports_effects() -> Res<()> {
+        let mut machine = Machine::with_inputs(vec![3, 0, 4, 0, 1105, 1, 9, 0, 0, 99], vec![42]);
+        let step = machine.step()?;
+        assert_eq!(step.input, Some(42));
+        assert_eq!(
+            step.write,
+            Some(Write {
+                addr: 0,
+                old: 3,
+                new: 42
+            })
+        );
+        assert_eq!(machine.step()?.output, Some(42));
+        assert_eq!(machine.step()?.branch, Some(true));
+        assert_eq!(machine.pc(), 9);
+        machine.run()?;
+        assert!(machine.is_halted());
+        assert_eq!(machine.steps(), 4);
+
+        let machine = Machine::new(vec![3, 0, 99]);
+        assert!(machine.needs_input());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_addresses() {
+        assert!(Machine::new(vec![1, -1, 0, 0, 99]).run().is_err());
+        assert!(Machine::new(vec![1, 0, 0, 50, 99]).run().is_err());
+        assert!(Machine::new(vec![3, 0, 99]).run().is_err());
+    }
+
+    fn fault(mut machine: Machine) -> Fault {
+        let error = machine.run().unwrap_err();
+        *error.downcast::<Fault>().unwrap()
+    }
+
+    #[test]
+    fn typed_faults() {
+        let machine = Machine::new(vec![1101, 0, 0, 0, 4, -7, 99]);
+        assert_eq!(
+            fault(machine),
+            Fault {
+                pc: 4,
+                kind: FaultKind::NegativeAddress(-7)
+            }
+        );
+        let machine = Machine::new(vec![1, 0, 0, 50, 99]);
+        assert_eq!(fault(machine).kind, FaultKind::OutOfBounds(50));
+
+        let program = vec![109, IntCode::MAX, 204, 1, 99];
+        let machine = Machine::with_dialect(program, Dialect::Full);
+        assert_eq!(
+            fault(machine),
+            Fault {
+                pc: 2,
+                kind: FaultKind::AddressOverflow {
+                    base: IntCode::MAX,
+                    offset: 1
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let program = vec![1101, 1, 2, 8, 4, 8, 99, 0, 0];
+        let mut machine = Machine::new(program.clone());
+        machine.set_limits(Limits::default().with_read_only(0..7));
+        assert!(machine.run().is_ok());
+        assert_eq!(machine.outputs(), &[3]);
+        let mut machine = Machine::new(program);
+        machine.set_limits(Limits::default().with_read_only(0..9));
+        assert_eq!(
+            fault(machine),
+            Fault {
+                pc: 0,
+                kind: FaultKind::ReadOnly(8)
+            }
+        );
+
+        let mut machine = Machine::new(vec![104, 1, 104, 2, 99]);
+        machine.set_limits(Limits::default().with_max_outputs(1));
+        assert_eq!(
+            fault(machine),
+            Fault {
+                pc: 2,
+                kind: FaultKind::OutputLimit(1)
+            }
+        );
+
+        let mut machine = Machine::with_dialect(vec![1101, 1, 2, 1000, 99], Dialect::Full);
+        machine.set_limits(Limits::default().with_max_memory(100));
+        assert_eq!(
+            fault(machine).to_string(),
+            "Address 1000 beyond memory limit 100 at 0"
+        );
+
+        // Growth is capped even with no limits set.
+        let addr = IntCode::MAX - 1;
+        let machine = Machine::with_dialect(vec![1101, 1, 1, addr, 99], Dialect::Full);
+        assert_eq!(
+            fault(machine).kind,
+            FaultKind::MemoryLimit {
+                addr,
+                limit: DEFAULT_MAX_MEMORY
+            }
+        );
+        let machine = Machine::with_dialect(vec![1101, 1, 1, 1 << 62, 99], Dialect::Full);
+        assert_eq!(
+            fault(machine).kind,
+            FaultKind::MemoryLimit {
+                addr: 1 << 62,
+                limit: DEFAULT_MAX_MEMORY
+            }
+        );
+    }
+
+    #[test]
+    fn dialects() -> Res<()> {
+        // Day 2 has no immediate mode, so 1002 is not a valid opcode.
+        let program = vec![1002, 4, 3, 4, 33];
+        assert!(Machine::with_dialect(program.clone(), Dialect::Day02)
+            .run()
+            .is_err());
+        let mut machine = Machine::with_dialect(program, Dialect::Day05);
+        machine.run()?;
+        assert_eq!(machine.memory()[4], 99);
+
+        // Outputs a copy of itself, using relative mode and memory past the program.
+        let quine = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ];
+        assert!(Machine::new(quine.clone()).run().is_err());
+        let mut machine = Machine::with_dialect(quine.clone(), Dialect::Full);
+        machine.run()?;
+        assert_eq!(machine.outputs(), quine.as_slice());
+
+        let mut machine = Machine::with_dialect(vec![104, 1125899906842624, 99], Dialect::Full);
+        machine.run()?;
+        assert_eq!(machine.outputs(), &[1125899906842624]);
+        Ok(())
+    }
+}
@@ -0,0 +1,567 @@
+use crate::instruction::{Instruction, Opcode, Parameter, ParameterMode, Role};
+use crate::machine::{alu, jump_target, Machine};
+use crate::registry::Registry;
+use crate::IntCode;
+use common::Res;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+
+/*
+ * Peephole optimisation for Intcode programs.
+ *
+ * The program is decoded by following control flow from address 0, so unreachable code is
+ * never translated. Every address that a reachable instruction can write to is collected
+ * first; instructions overlapping those addresses are left to the interpreter, which keeps
+ * self-modifying programs behaving exactly as before. Instructions using relative parameters
+ * are interpreted too, and a program that writes through one isn't optimised at all, since
+ * its writes can't be known in advance. Custom opcodes may do anything, so reaching one
+ * hands the rest of the run to the interpreter.
+ */
+
+/// A source operand after analysis.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Operand {
+    /// Known before the program runs.
+    Const(IntCode),
+    /// Read from this address at run time.
+    Addr(IntCode),
+}
+
+/// Translated form of the instruction found at a given address.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Op {
+    /// Execute the original instruction with the interpreter.
+    Interpret,
+    /// The instruction may be rewritten at run time; interpret the rest of the program.
+    Bail,
+    /// Arithmetic or comparison with constant operands.
+    Store {
+        dst: IntCode,
+        value: IntCode,
+        next: usize,
+    },
+    Compute {
+        opcode: Opcode,
+        src0: Operand,
+        src1: Operand,
+        dst: IntCode,
+        next: usize,
+    },
+    Jump {
+        target: Operand,
+    },
+    /// Conditional jump that could not be folded.
+    Branch {
+        if_true: bool,
+        condition: Operand,
+        target: Operand,
+        next: usize,
+    },
+    /// `lt`/`eq` followed by a `jt`/`jf` on its result.
+    CompareBranch {
+        opcode: Opcode,
+        src0: Operand,
+        src1: Operand,
+        dst: IntCode,
+        if_true: bool,
+        target: Operand,
+        next: usize,
+    },
+    /// Conditional jump that is never taken.
+    Skip {
+        next: usize,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Stats {
+    /// Reachable instructions.
+    pub instructions: usize,
+    /// Instructions whose operands were all constant.
+    pub folded: usize,
+    /// Compare-and-jump pairs fused into one op.
+    pub fused: usize,
+    /// Instructions left to the interpreter.
+    pub protected: usize,
+}
+
+/// An optimised program, ready to run on a machine loaded with the original code.
+#[derive(Clone, Debug)]
+pub struct Optimized {
+    registry: Arc<Registry>,
+    program: Vec<IntCode>,
+    ops: Vec<Option<Op>>,
+    pub stats: Stats,
+}
+
+/// Analyse and optimise the given program for machines sharing the registry.
+pub fn optimize(program: &[IntCode], registry: &Arc<Registry>) -> Optimized {
+    // Grow the set of written addresses until it covers every reachable write. Each round
+    // can only add addresses, since fewer constants means fewer folded branches.
+    let mut written: HashSet<usize> = HashSet::new();
+    let reachable = loop {
+        let reachable = traverse(program, registry, &written);
+        let mut next_written = written.clone();
+        for instruction in reachable.values() {
+            match instruction.destination() {
+                Some(param) if param.mode == ParameterMode::Relative => {
+                    return Optimized {
+                        registry: registry.clone(),
+                        program: program.to_vec(),
+                        ops: vec![],
+                        stats: Stats::default(),
+                    };
+                }
+                Some(param) if param.value >= 0 => {
+                    next_written.insert(param.value as usize);
+                }
+                _ => {}
+            }
+        }
+        if next_written.len() == written.len() {
+            break reachable;
+        }
+        written = next_written;
+    };
+
+    let analysis = Analysis {
+        program,
+        written: &written,
+    };
+    let mut stats = Stats::default();
+    let mut ops: Vec<Option<Op>> = vec![None; program.len()];
+    for (&pc, instruction) in &reachable {
+        stats.instructions += 1;
+        let op = analysis.translate(pc, instruction, &reachable);
+        match &op {
+            Op::Interpret | Op::Bail => stats.protected += 1,
+            Op::Store { .. } | Op::Jump { .. } | Op::Skip { .. } => stats.folded += 1,
+            Op::CompareBranch { .. } => stats.fused += 1,
+            _ => {}
+        }
+        ops[pc] = Some(op);
+    }
+
+    Optimized {
+        registry: registry.clone(),
+        program: program.to_vec(),
+        ops,
+        stats,
+    }
+}
+
+/// Decode every instruction reachable from address 0.
+fn traverse(
+    program: &[IntCode],
+    registry: &Registry,
+    written: &HashSet<usize>,
+) -> BTreeMap<usize, Instruction> {
+    let analysis = Analysis { program, written };
+    let mut reachable = BTreeMap::new();
+    let mut pending = vec![0];
+    while let Some(pc) = pending.pop() {
+        if reachable.contains_key(&pc) {
+            continue;
+        }
+        // Anything that fails to decode is left for the interpreter to report.
+        let instruction = match registry.decode(program, pc) {
+            Ok(instruction) => instruction,
+            Err(_) => continue,
+        };
+        pending.extend(analysis.successors(pc, &instruction));
+        reachable.insert(pc, instruction);
+    }
+    reachable
+}
+
+struct Analysis<'a> {
+    program: &'a [IntCode],
+    written: &'a HashSet<usize>,
+}
+
+impl<'a> Analysis<'a> {
+    /// True if the opcode or destination of the instruction may change at run time.
+    fn is_volatile(&self, pc: usize, instruction: &Instruction) -> bool {
+        self.written.contains(&pc)
+            || (instruction.params.iter().enumerate())
+                .filter(|(_, param)| param.role == Role::Write)
+                .any(|(index, _)| self.written.contains(&(pc + 1 + index)))
+    }
+
+    /// True if the instruction must be left to the interpreter: any of its cells may change
+    /// at run time, or it addresses memory relative to the relative base.
+    fn is_protected(&self, pc: usize, instruction: &Instruction) -> bool {
+        instruction
+            .params
+            .iter()
+            .any(|param| param.mode == ParameterMode::Relative)
+            || (pc..pc + instruction.size()).any(|cell| self.written.contains(&cell))
+    }
+
+    fn operand(&self, param: Parameter) -> Operand {
+        match param.mode {
+            ParameterMode::Immediate => Operand::Const(param.value),
+            ParameterMode::Position => {
+                let addr = param.value;
+                if addr >= 0
+                    && (addr as usize) < self.program.len()
+                    && !self.written.contains(&(addr as usize))
+                {
+                    Operand::Const(self.program[addr as usize])
+                } else {
+                    Operand::Addr(addr)
+                }
+            }
+            ParameterMode::Relative => unreachable!("relative parameters are never translated"),
+        }
+    }
+
+    fn successors(&self, pc: usize, instruction: &Instruction) -> Vec<usize> {
+        let next = pc + instruction.size();
+        if self.is_volatile(pc, instruction) {
+            return vec![];
+        }
+        match instruction.opcode {
+            Opcode::Halt | Opcode::Custom(_) => vec![],
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+                if self.is_protected(pc, instruction) {
+                    return vec![next];
+                }
+                let if_true = instruction.opcode == Opcode::JumpIfTrue;
+                let target = match self.operand(instruction.params[1]) {
+                    Operand::Const(target) if target >= 0 => Some(target as usize),
+                    _ => None,
+                };
+                match self.operand(instruction.params[0]) {
+                    Operand::Const(condition) if (condition != 0) == if_true => {
+                        target.into_iter().collect()
+                    }
+                    Operand::Const(_) => vec![next],
+                    Operand::Addr(_) => Some(next).into_iter().chain(target).collect(),
+                }
+            }
+            _ => vec![next],
+        }
+    }
+
+    fn translate(
+        &self,
+        pc: usize,
+        instruction: &Instruction,
+        reachable: &BTreeMap<usize, Instruction>,
+    ) -> Op {
+        if self.is_volatile(pc, instruction) || matches!(instruction.opcode, Opcode::Custom(_)) {
+            return Op::Bail;
+        }
+        if self.is_protected(pc, instruction) {
+            return Op::Interpret;
+        }
+        let params = &instruction.params;
+        let next = pc + instruction.size();
+        match instruction.opcode {
+            Opcode::Add | Opcode::Multiply | Opcode::LessThan | Opcode::Equals => {
+                let opcode = instruction.opcode;
+                let src0 = self.operand(params[0]);
+                let src1 = self.operand(params[1]);
+                let dst = params[2].value;
+                if let (Operand::Const(a), Operand::Const(b)) = (src0, src1) {
+                    // Leave overflow for the interpreter to report.
+                    if let Ok(value) = alu(opcode, a, b, pc) {
+                        return Op::Store { dst, value, next };
+                    }
+                    return Op::Interpret;
+                }
+                if opcode == Opcode::LessThan || opcode == Opcode::Equals {
+                    if let Some(op) = self.fuse(opcode, src0, src1, dst, next, reachable) {
+                        return op;
+                    }
+                }
+                Op::Compute {
+                    opcode,
+                    src0,
+                    src1,
+                    dst,
+                    next,
+                }
+            }
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+                let if_true = instruction.opcode == Opcode::JumpIfTrue;
+                let condition = self.operand(params[0]);
+                let target = self.operand(params[1]);
+                match condition {
+                    Operand::Const(value) if (value != 0) == if_true => Op::Jump { target },
+                    Operand::Const(_) => Op::Skip { next },
+                    Operand::Addr(_) => Op::Branch {
+                        if_true,
+                        condition,
+                        target,
+                        next,
+                    },
+                }
+            }
+            // I/O and halting gain nothing from translation.
+            _ => Op::Interpret,
+        }
+    }
+
+    /// Fuse a comparison with an immediately following jump on its result.
+    fn fuse(
+        &self,
+        opcode: Opcode,
+        src0: Operand,
+        src1: Operand,
+        dst: IntCode,
+        next: usize,
+        reachable: &BTreeMap<usize, Instruction>,
+    ) -> Option<Op> {
+        let jump = reachable.get(&next)?;
+        if jump.opcode != Opcode::JumpIfTrue && jump.opcode != Opcode::JumpIfFalse {
+            return None;
+        }
+        if self.is_protected(next, jump) {
+            return None;
+        }
+        let condition = jump.params[0];
+        if condition.mode != ParameterMode::Position || condition.value != dst {
+            return None;
+        }
+        Some(Op::CompareBranch {
+            opcode,
+            src0,
+            src1,
+            dst,
+            if_true: jump.opcode == Opcode::JumpIfTrue,
+            target: self.operand(jump.params[1]),
+            next: next + jump.size(),
+        })
+    }
+}
+
+impl Optimized {
+    /// The translated op at the given address, if the address holds reachable code.
+    pub fn op(&self, pc: usize) -> Option<&Op> {
+        self.ops.get(pc).and_then(|op| op.as_ref())
+    }
+
+    /// Run the program to completion. Machines that are not at the start of the original
+    /// program are simply interpreted.
+    pub fn run(&self, machine: &mut Machine) -> Res<()> {
+        if machine.pc != 0
+            || !Arc::ptr_eq(&machine.registry, &self.registry)
+            || machine.memory != self.program
+        {
+            return machine.run();
+        }
+        while !machine.is_halted() {
+            let pc = machine.pc;
+            let op = match self.op(pc) {
+                Some(op) => op,
+                // Reached through a computed jump; the analysis can't vouch for this code.
+                None => return machine.run(),
+            };
+            match op {
+                Op::Bail => return machine.run(),
+                Op::Interpret => {
+                    machine.step()?;
+                }
+                Op::Store { dst, value, next } => {
+                    machine.write(*dst, *value)?;
+                    machine.pc = *next;
+                    machine.steps += 1;
+                }
+                Op::Compute {
+                    opcode,
+                    src0,
+                    src1,
+                    dst,
+                    next,
+                } => {
+                    let value = alu(*opcode, load(machine, *src0)?, load(machine, *src1)?, pc)?;
+                    machine.write(*dst, value)?;
+                    machine.pc = *next;
+                    machine.steps += 1;
+                }
+                Op::Jump { target } => {
+                    machine.pc = jump_target(load(machine, *target)?, pc)?;
+                    machine.steps += 1;
+                }
+                Op::Branch {
+                    if_true,
+                    condition,
+                    target,
+                    next,
+                } => {
+                    let condition = load(machine, *condition)?;
+                    let target = load(machine, *target)?;
+                    machine.pc = if (condition != 0) == *if_true {
+                        jump_target(target, pc)?
+                    } else {
+                        *next
+                    };
+                    machine.steps += 1;
+                }
+                Op::CompareBranch {
+                    opcode,
+                    src0,
+                    src1,
+                    dst,
+                    if_true,
+                    target,
+                    next,
+                } => {
+                    let value = alu(*opcode, load(machine, *src0)?, load(machine, *src1)?, pc)?;
+                    machine.write(*dst, value)?;
+                    let target = load(machine, *target)?;
+                    machine.pc = if (value != 0) == *if_true {
+                        jump_target(target, pc)?
+                    } else {
+                        *next
+                    };
+                    machine.steps += 2;
+                }
+                Op::Skip { next } => {
+                    machine.pc = *next;
+                    machine.steps += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn load(machine: &Machine, operand: Operand) -> Res<IntCode> {
+    match operand {
+        Operand::Const(value) => Ok(value),
+        Operand::Addr(addr) => machine.read(Parameter {
+            mode: ParameterMode::Position,
+            role: Role::Read,
+            value: addr,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::instruction::Dialect;
+
+    fn machine(program: &[IntCode], registry: &Arc<Registry>, inputs: &[IntCode]) -> Machine {
+        let mut machine = Machine::with_registry(program.to_vec(), registry.clone());
+        for &input in inputs {
+            machine.push_input(input);
+        }
+        machine
+    }
+
+    /// Run the program both ways and check the results agree.
+    fn run_both(program: &[IntCode], inputs: Vec<IntCode>) -> Res<(Optimized, Machine)> {
+        let registry = Arc::new(Registry::new(Dialect::Day05));
+        let mut expected = machine(program, &registry, &inputs);
+        expected.run()?;
+
+        let optimized = optimize(program, &registry);
+        let mut machine = machine(program, &registry, &inputs);
+        optimized.run(&mut machine)?;
+        assert_eq!(machine.outputs(), expected.outputs());
+        assert_eq!(machine.memory(), expected.memory());
+        assert_eq!(machine.steps(), expected.steps());
+        Ok((optimized, machine))
+    }
+
+    #[test]
+    fn folds_constants() -> Res<()> {
+        let (optimized, machine) = run_both(&[1101, 2, 3, 7, 4, 7, 99, 0], vec![])?;
+        assert_eq!(machine.outputs(), &[5]);
+        assert_eq!(
+            optimized.op(0),
+            Some(&Op::Store {
+                dst: 7,
+                value: 5,
+                next: 4
+            })
+        );
+        assert_eq!(optimized.stats.folded, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn fuses_compare_and_jump() -> Res<()> {
+        let program = vec![
+            3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0,
+            0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4,
+            20, 1105, 1, 46, 98, 99,
+        ];
+        for &(input, output) in &[(7, 999), (8, 1000), (9, 1001)] {
+            let (optimized, machine) = run_both(&program, vec![input])?;
+            assert_eq!(machine.outputs(), &[output]);
+            assert_eq!(optimized.stats.fused, 2);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn skips_unreachable_code() -> Res<()> {
+        let (optimized, machine) = run_both(&[1105, 1, 5, 77, 77, 4, 0, 99], vec![])?;
+        assert_eq!(machine.outputs(), &[1105]);
+        assert_eq!(optimized.stats.instructions, 3);
+        assert_eq!(optimized.op(3), None);
+        Ok(())
+    }
+
+    #[test]
+    fn protects_self_modifying_code() -> Res<()> {
+        let program = vec![3, 3, 1108, -1, 8, 3, 4, 3, 99];
+        for &(input, output) in &[(8, 1), (1, 0)] {
+            let (optimized, machine) = run_both(&program, vec![input])?;
+            assert_eq!(machine.outputs(), &[output]);
+            assert_eq!(optimized.op(2), Some(&Op::Interpret));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn relative_writes_disable_optimization() -> Res<()> {
+        let program = vec![109, 10, 21101, 2, 3, 0, 4, 10, 99, 0, 0];
+        let registry = Arc::new(Registry::new(Dialect::Full));
+        let optimized = optimize(&program, &registry);
+        assert_eq!(optimized.op(0), None);
+        let mut machine = machine(&program, &registry, &[]);
+        optimized.run(&mut machine)?;
+        assert_eq!(machine.outputs(), &[5]);
+        Ok(())
+    }
+
+    #[test]
+    fn bails_on_rewritten_opcode() -> Res<()> {
+        // The add turns the instruction at 4 into a halt.
+        let (optimized, machine) = run_both(&[1101, 0, 99, 4, 1, 0, 0, 9, 99, 0], vec![])?;
+        assert_eq!(optimized.op(4), Some(&Op::Bail));
+        assert_eq!(machine.memory()[9], 0);
+        Ok(())
+    }
+
+    fn double(context: &mut crate::registry::Context) -> Res<()> {
+        let value = context.arg(0)?;
+        context.store(1, value * 2)
+    }
+
+    #[test]
+    fn bails_on_custom_opcodes() -> Res<()> {
+        let mut registry = Registry::new(Dialect::Day05);
+        registry.register(42, "dbl", &[Role::Read, Role::Write], double)?;
+        let registry = Arc::new(registry);
+        let program = [1101, 2, 3, 10, 142, 21, 10, 4, 10, 99, 0];
+        let optimized = optimize(&program, &registry);
+        assert_eq!(optimized.op(4), Some(&Op::Bail));
+        assert_eq!(optimized.op(7), None);
+
+        let mut machine = machine(&program, &registry, &[]);
+        optimized.run(&mut machine)?;
+        assert_eq!(machine.outputs(), &[42]);
+
+        // A machine with a different registry is simply interpreted.
+        let mut other = Machine::new(program.to_vec());
+        assert!(optimized.run(&mut other).is_err());
+        Ok(())
+    }
+}
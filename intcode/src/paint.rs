@@ -0,0 +1,192 @@
+use crate::device::Device;
+use crate::grid::{bounds, Direction, Position};
+use crate::IntCode;
+use common::{error, Res};
+use std::collections::{HashMap, HashSet};
+
+/*
+ * The hull-painting robot.
+ *
+ * The program reads the colour of the panel under the robot (0 black, 1 white), then outputs
+ * pairs of values: the colour to paint that panel, and which way to turn (0 left, 1 right),
+ * after which the robot moves forward one panel. The robot starts at (0, 0) facing up, on a
+ * hull that is black everywhere else.
+ */
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Colour {
+    Black,
+    White,
+}
+
+impl Colour {
+    fn from_int(value: IntCode) -> Res<Colour> {
+        match value {
+            0 => Ok(Colour::Black),
+            1 => Ok(Colour::White),
+            _ => error(format!("Invalid colour: {}", value)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PaintRobot {
+    panels: HashMap<Position, Colour>,
+    painted: HashSet<Position>,
+    position: Position,
+    direction: Direction,
+    /// Colour output by the program, waiting for its turn instruction.
+    paint: Option<Colour>,
+}
+
+impl PaintRobot {
+    /// A robot starting on a panel of the given colour.
+    pub fn new(start: Colour) -> PaintRobot {
+        let mut panels = HashMap::new();
+        panels.insert((0, 0), start);
+        PaintRobot {
+            panels,
+            painted: HashSet::new(),
+            position: (0, 0),
+            direction: Direction::Up,
+            paint: None,
+        }
+    }
+
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    pub fn colour(&self, position: Position) -> Colour {
+        self.panels.get(&position).cloned().unwrap_or(Colour::Black)
+    }
+
+    /// Number of panels painted at least once.
+    pub fn painted(&self) -> usize {
+        self.painted.len()
+    }
+
+    /// The hull as text, `#` for white and `.` for black, cropped to the panels the robot
+    /// has seen.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        if let Some(((min_x, min_y), (max_x, max_y))) = bounds(self.panels.keys()) {
+            for y in min_y..=max_y {
+                for x in min_x..=max_x {
+                    out.push(match self.colour((x, y)) {
+                        Colour::White => '#',
+                        Colour::Black => '.',
+                    });
+                }
+                out.push('\n');
+            }
+        }
+        out
+    }
+
+    /// The hull as a binary PPM image, with each panel `scale` pixels square.
+    pub fn ppm(&self, scale: usize) -> Vec<u8> {
+        let ((min_x, min_y), (max_x, max_y)) = bounds(self.panels.keys()).unwrap_or_default();
+        let width = (max_x - min_x + 1) as usize;
+        let height = (max_y - min_y + 1) as usize;
+        let mut bytes = format!("P6\n{} {}\n255\n", width * scale, height * scale).into_bytes();
+        for y in min_y..=max_y {
+            let mut row = vec![];
+            for x in min_x..=max_x {
+                let value = match self.colour((x, y)) {
+                    Colour::White => 255,
+                    Colour::Black => 0,
+                };
+                row.extend(std::iter::repeat_n(value, 3 * scale));
+            }
+            for _ in 0..scale {
+                bytes.extend(&row);
+            }
+        }
+        bytes
+    }
+}
+
+impl Device for PaintRobot {
+    fn input(&mut self) -> Res<IntCode> {
+        Ok(match self.colour(self.position) {
+            Colour::Black => 0,
+            Colour::White => 1,
+        })
+    }
+
+    fn output(&mut self, value: IntCode) -> Res<()> {
+        let colour = match self.paint.take() {
+            Some(colour) => colour,
+            None => {
+                self.paint = Some(Colour::from_int(value)?);
+                return Ok(());
+            }
+        };
+        self.panels.insert(self.position, colour);
+        self.painted.insert(self.position);
+        self.direction = match value {
+            0 => self.direction.turn_left(),
+            1 => self.direction.turn_right(),
+            _ => return error(format!("Invalid turn: {}", value)),
+        };
+        self.position = self.direction.step(self.position);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::device::run_device;
+    use crate::machine::Machine;
+    use crate::registry::Registry;
+    use crate::Dialect;
+
+    #[test]
+    fn example() -> Res<()> {
+        let mut robot = PaintRobot::new(Colour::Black);
+        for &value in &[1, 0, 0, 0, 1, 0, 1, 0, 0, 1, 1, 0, 1, 0] {
+            robot.output(value)?;
+        }
+        assert_eq!(robot.painted(), 6);
+        assert_eq!(robot.position(), (0, -1));
+        assert_eq!(robot.render(), "..#\n..#\n##.\n");
+        assert!(robot.output(2).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn program() -> Res<()> {
+        // Paints a white square by turning right at every corner, then repaints the start
+        // with the colour it reads there and turns left.
+        let source = "
+                    in [colour]
+            loop:   out 1
+                    out 1
+                    add [count], 1, [count]
+                    lt [count], 4, [more]
+                    jt [more], loop
+                    in [colour]
+                    out [colour]
+                    out 0
+                    hlt
+            count:  data 0
+            more:   data 0
+            colour: data 0
+        ";
+        let program = assemble(source, &Registry::new(Dialect::Day05))?;
+        let mut robot = PaintRobot::new(Colour::Black);
+        run_device(&mut Machine::new(program), &mut robot)?;
+        assert_eq!(robot.painted(), 4);
+        assert_eq!(robot.render(), "##\n##\n");
+        assert_eq!(robot.colour((0, 0)), Colour::White);
+        assert_eq!(robot.position(), (-1, 0));
+
+        let ppm = robot.ppm(2);
+        assert!(ppm.starts_with(b"P6\n4 4\n255\n"));
+        assert_eq!(ppm.len(), 11 + 4 * 4 * 3);
+        Ok(())
+    }
+}
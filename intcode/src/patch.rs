@@ -0,0 +1,45 @@
+use crate::IntCode;
+use common::{error, Res};
+
+/// A value to write over a program cell before it runs.
+pub type Patch = (usize, IntCode);
+
+/// Copy the program with the patches applied.
+pub fn patch(program: &[IntCode], patches: &[Patch]) -> Res<Vec<IntCode>> {
+    let mut program = program.to_vec();
+    for &(addr, value) in patches {
+        match program.get_mut(addr) {
+            Some(cell) => *cell = value,
+            None => return error(format!("Patch address {} out of range", addr)),
+        }
+    }
+    Ok(program)
+}
+
+/// Parse a patch written as "ADDR=VALUE", such as "0=2".
+pub fn parse_patch(value: &str) -> Res<Patch> {
+    let parts: Vec<&str> = value.split('=').collect();
+    if parts.len() != 2 {
+        return error(format!("Expected ADDR=VALUE, got \"{}\"", value));
+    }
+    Ok((parts[0].trim().parse()?, parts[1].trim().parse()?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn patches() -> Res<()> {
+        assert_eq!(
+            patch(&[1, 0, 0, 0, 99], &[(1, 12), (2, 2)])?,
+            vec![1, 12, 2, 0, 99]
+        );
+        assert!(patch(&[99], &[(1, 0)]).is_err());
+        assert_eq!(parse_patch("0=2")?, (0, 2));
+        assert_eq!(parse_patch(" 3 = -1 ")?, (3, -1));
+        assert!(parse_patch("0").is_err());
+        assert!(parse_patch("x=1").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,286 @@
+use crate::grid::Position;
+use crate::machine::Machine;
+use crate::IntCode;
+use common::{error, Res};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::thread;
+
+/*
+ * Probing programs that answer "is (x, y) affected?".
+ *
+ * Such programs read x then y, output 1 or 0, and halt, so every query needs a fresh copy of
+ * the program. The probe keeps the pristine machine, copies it for each query and remembers
+ * every answer.
+ */
+
+pub struct Probe {
+    machine: Machine,
+    cache: HashMap<Position, bool>,
+    threads: usize,
+}
+
+/// Ask a fresh copy of the machine about one point.
+fn ask(machine: &Machine, (x, y): Position) -> Res<bool> {
+    let mut machine = machine.clone();
+    machine.push_input(x);
+    machine.push_input(y);
+    machine.run()?;
+    match machine.outputs() {
+        [0] => Ok(false),
+        [1] => Ok(true),
+        outputs => error(format!("Unexpected probe output: {:?}", outputs)),
+    }
+}
+
+impl Probe {
+    pub fn new(machine: Machine) -> Probe {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        Probe {
+            machine,
+            cache: HashMap::new(),
+            threads,
+        }
+    }
+
+    /// Run at most this many queries at once.
+    pub fn with_threads(mut self, threads: usize) -> Probe {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Number of points asked about so far; repeated queries are answered from the cache.
+    pub fn queries(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn query(&mut self, position: Position) -> Res<bool> {
+        if let Some(&affected) = self.cache.get(&position) {
+            return Ok(affected);
+        }
+        let affected = ask(&self.machine, position)?;
+        self.cache.insert(position, affected);
+        Ok(affected)
+    }
+
+    /// Answer many queries, running the ones not already cached in parallel.
+    pub fn query_all(&mut self, positions: &[Position]) -> Res<Vec<bool>> {
+        let mut missing: Vec<Position> = positions
+            .iter()
+            .filter(|position| !self.cache.contains_key(position))
+            .cloned()
+            .collect();
+        missing.sort_unstable();
+        missing.dedup();
+
+        if !missing.is_empty() {
+            let chunk_size = missing.len().div_ceil(self.threads);
+            let machine = &self.machine;
+            // Errors aren't Send, so they cross back from the threads as messages.
+            let answers: Vec<Result<Vec<bool>, String>> = thread::scope(|scope| {
+                let handles: Vec<_> = missing
+                    .chunks(chunk_size)
+                    .map(|chunk| {
+                        scope.spawn(move || {
+                            chunk
+                                .iter()
+                                .map(|&position| ask(machine, position).map_err(|e| e.to_string()))
+                                .collect()
+                        })
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|handle| {
+                        handle
+                            .join()
+                            .unwrap_or_else(|_| Err("Probe panicked".into()))
+                    })
+                    .collect()
+            });
+            let mut answers = answers.into_iter();
+            for chunk in missing.chunks(chunk_size) {
+                let chunk_answers = match answers.next() {
+                    Some(Ok(chunk_answers)) => chunk_answers,
+                    Some(Err(message)) => return error(message),
+                    None => return error("Missing probe answers"),
+                };
+                self.cache.extend(chunk.iter().cloned().zip(chunk_answers));
+            }
+        }
+        Ok(positions
+            .iter()
+            .map(|position| self.cache[position])
+            .collect())
+    }
+
+    /// Number of affected points in the region.
+    pub fn count(&mut self, xs: Range<IntCode>, ys: Range<IntCode>) -> Res<usize> {
+        let positions: Vec<Position> = ys.flat_map(|y| xs.clone().map(move |x| (x, y))).collect();
+        Ok(self
+            .query_all(&positions)?
+            .into_iter()
+            .filter(|&a| a)
+            .count())
+    }
+
+    /// The top-left corner of the first `size` by `size` square, scanning down from the
+    /// origin, that lies wholly within a beam widening away from the origin. Rows are searched
+    /// until the square's bottom edge reaches `max_y`, and each row as far as `max_x`.
+    pub fn find_square(
+        &mut self,
+        size: IntCode,
+        max_x: IntCode,
+        max_y: IntCode,
+    ) -> Res<Option<Position>> {
+        if size <= 0 {
+            return error(format!("Invalid square size: {}", size));
+        }
+        // The beam's left edge only moves right as y grows, so each row's search starts from
+        // the previous row's edge.
+        let mut left = 0;
+        for bottom in size - 1..=max_y {
+            let x = match self.find_edge(left, max_x, bottom)? {
+                Some(x) => x,
+                // Near the origin the beam can miss whole rows.
+                None => continue,
+            };
+            left = x;
+            let top = bottom - (size - 1);
+            if self.query((x + size - 1, top))? {
+                return Ok(Some((x, top)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The first affected x in row `y`, from `from` to `max_x`. Points are asked a batch at a
+    /// time, one per thread.
+    fn find_edge(&mut self, from: IntCode, max_x: IntCode, y: IntCode) -> Res<Option<IntCode>> {
+        let batch = self.threads as IntCode;
+        let mut start = from;
+        while start <= max_x {
+            let end = start.saturating_add(batch - 1).min(max_x);
+            let positions: Vec<Position> = (start..=end).map(|x| (x, y)).collect();
+            if let Some(index) = self.query_all(&positions)?.iter().position(|&a| a) {
+                return Ok(Some(start + index as IntCode));
+            }
+            start = match end.checked_add(1) {
+                Some(start) => start,
+                None => break,
+            };
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::registry::Registry;
+    use crate::Dialect;
+
+    // Affected where y/2 <= x <= 2y.
+    const BEAM: &str = "
+        in [x]
+        in [y]
+        mul [x], 2, [a]
+        lt [a], [y], [a]
+        mul [y], 2, [b]
+        lt [b], [x], [b]
+        add [a], [b], [a]
+        eq [a], 0, [a]
+        out [a]
+        hlt
+        x: data 0
+        y: data 0
+        a: data 0
+        b: data 0
+    ";
+
+    // Affected where 2y <= x <= 3y, so right of the diagonal.
+    const SHALLOW_BEAM: &str = "
+        in [x]
+        in [y]
+        mul [y], 2, [a]
+        lt [x], [a], [a]
+        mul [y], 3, [b]
+        lt [b], [x], [b]
+        add [a], [b], [a]
+        eq [a], 0, [a]
+        out [a]
+        hlt
+        x: data 0
+        y: data 0
+        a: data 0
+        b: data 0
+    ";
+
+    fn affected((x, y): Position) -> bool {
+        2 * x >= y && 2 * y >= x
+    }
+
+    fn probe() -> Probe {
+        beam_probe(BEAM)
+    }
+
+    fn beam_probe(source: &str) -> Probe {
+        let program = assemble(source, &Registry::new(Dialect::Day05)).unwrap();
+        Probe::new(Machine::new(program)).with_threads(4)
+    }
+
+    fn first_square<F: Fn(Position) -> bool>(
+        affected: F,
+        size: IntCode,
+        max_x: IntCode,
+        max_y: IntCode,
+    ) -> Option<Position> {
+        let fits = |(x, y): Position| {
+            affected((x, y))
+                && affected((x + size - 1, y))
+                && affected((x, y + size - 1))
+                && affected((x + size - 1, y + size - 1))
+        };
+        (0..=max_y - (size - 1))
+            .flat_map(|y| (0..=max_x - (size - 1)).map(move |x| (x, y)))
+            .find(|&p| fits(p))
+    }
+
+    #[test]
+    fn queries_and_cache() -> Res<()> {
+        let mut probe = probe();
+        assert!(probe.query((3, 4))?);
+        assert!(!probe.query((9, 4))?);
+        assert_eq!(
+            probe.query_all(&[(3, 4), (1, 1), (1, 1), (0, 5)])?,
+            vec![true, true, true, false]
+        );
+        assert_eq!(probe.queries(), 4);
+
+        let expected = (0..20)
+            .flat_map(|y| (0..30).map(move |x| (x, y)))
+            .filter(|&p| affected(p))
+            .count();
+        assert_eq!(probe.count(0..30, 0..20)?, expected);
+        assert_eq!(probe.queries(), 600);
+        Ok(())
+    }
+
+    #[test]
+    fn find_square() -> Res<()> {
+        let size = 5;
+        let expected = first_square(affected, size, 50, 50);
+        assert!(expected.is_some());
+        assert_eq!(probe().find_square(size, 50, 50)?, expected);
+        assert_eq!(probe().find_square(size, 50, 3)?, None);
+
+        // The square lies further right than it lies down.
+        let shallow = |(x, y): Position| 2 * y <= x && x <= 3 * y;
+        let expected = first_square(shallow, 3, 100, 10);
+        assert_eq!(expected, Some((16, 6)));
+        assert_eq!(beam_probe(SHALLOW_BEAM).find_square(3, 100, 10)?, expected);
+        assert_eq!(beam_probe(SHALLOW_BEAM).find_square(3, 15, 10)?, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,421 @@
+use crate::hostcall::HostCalls;
+use crate::instruction::{Dialect, Instruction, Opcode, Parameter, ParameterMode, Role};
+use crate::machine::{alu, jump_target, Machine, Step};
+use crate::IntCode;
+use common::{error, Res};
+use std::sync::MutexGuard;
+
+/*
+ * The opcode registry.
+ *
+ * Every opcode a machine understands, built-in or not, is described by a `Spec`: its
+ * mnemonic, the role of each parameter and the handler that executes it. The interpreter,
+ * assembler and disassembler all work from the registry, so registering a new opcode is
+ * all it takes to extend the instruction set:
+ *
+ *     fn double(context: &mut Context) -> Res<()> {
+ *         let value = context.arg(0)?;
+ *         context.store(1, value * 2)
+ *     }
+ *
+ *     let mut registry = Registry::new(Dialect::Day05);
+ *     registry.register(42, "dbl", &[Role::Read, Role::Write], double)?;
+ */
+
+/// Executes one instruction.
+pub type Handler = fn(&mut Context) -> Res<()>;
+
+#[derive(Clone, Debug)]
+pub struct Spec {
+    pub opcode: Opcode,
+    pub mnemonic: &'static str,
+    pub roles: Vec<Role>,
+    pub handler: Handler,
+}
+
+#[derive(Clone, Debug)]
+pub struct Registry {
+    dialect: Dialect,
+    /// Indexed by opcode; opcodes are the two lowest digits of an instruction.
+    specs: Vec<Option<Spec>>,
+}
+
+const BUILTINS: &[(Opcode, &str, &[Role], Handler)] = &[
+    (
+        Opcode::Add,
+        "add",
+        &[Role::Read, Role::Read, Role::Write],
+        arithmetic,
+    ),
+    (
+        Opcode::Multiply,
+        "mul",
+        &[Role::Read, Role::Read, Role::Write],
+        arithmetic,
+    ),
+    (Opcode::Input, "in", &[Role::Write], input),
+    (Opcode::Output, "out", &[Role::Read], output),
+    (Opcode::JumpIfTrue, "jt", &[Role::Read, Role::Read], jump),
+    (Opcode::JumpIfFalse, "jf", &[Role::Read, Role::Read], jump),
+    (
+        Opcode::LessThan,
+        "lt",
+        &[Role::Read, Role::Read, Role::Write],
+        arithmetic,
+    ),
+    (
+        Opcode::Equals,
+        "eq",
+        &[Role::Read, Role::Read, Role::Write],
+        arithmetic,
+    ),
+    (
+        Opcode::AdjustRelativeBase,
+        "arb",
+        &[Role::Read],
+        adjust_relative_base,
+    ),
+    (Opcode::Halt, "hlt", &[], halt),
+];
+
+impl Registry {
+    /// A registry holding the dialect's built-in opcodes.
+    pub fn new(dialect: Dialect) -> Registry {
+        let mut registry = Registry {
+            dialect,
+            specs: vec![None; 100],
+        };
+        for &(opcode, mnemonic, roles, handler) in BUILTINS {
+            if dialect.supports(opcode) {
+                registry.insert(opcode, mnemonic, roles, handler);
+            }
+        }
+        registry
+    }
+
+    pub fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    /// Add a custom opcode. Opcodes and mnemonics must not already be in use.
+    pub fn register(
+        &mut self,
+        code: IntCode,
+        mnemonic: &'static str,
+        roles: &[Role],
+        handler: Handler,
+    ) -> Res<()> {
+        if code <= 0 || code >= 100 {
+            return error(format!("Opcode out of range: {}", code));
+        }
+        if let Some(spec) = self.spec(code) {
+            return error(format!("Opcode {} is already {}", code, spec.mnemonic));
+        }
+        if self.by_mnemonic(mnemonic).is_some() {
+            return error(format!("Mnemonic already in use: {}", mnemonic));
+        }
+        // Even codes a richer dialect would use are custom here; only built-ins get the
+        // optimiser's special treatment.
+        self.insert(Opcode::Custom(code), mnemonic, roles, handler);
+        Ok(())
+    }
+
+    fn insert(&mut self, opcode: Opcode, mnemonic: &'static str, roles: &[Role], handler: Handler) {
+        self.specs[opcode.code() as usize] = Some(Spec {
+            opcode,
+            mnemonic,
+            roles: roles.to_vec(),
+            handler,
+        });
+    }
+
+    pub fn spec(&self, code: IntCode) -> Option<&Spec> {
+        if code < 0 || code as usize >= self.specs.len() {
+            return None;
+        }
+        self.specs[code as usize].as_ref()
+    }
+
+    pub fn by_mnemonic(&self, mnemonic: &str) -> Option<&Spec> {
+        self.specs().find(|spec| spec.mnemonic == mnemonic)
+    }
+
+    pub fn specs(&self) -> impl Iterator<Item = &Spec> {
+        self.specs.iter().filter_map(|spec| spec.as_ref())
+    }
+
+    /// Decode the instruction at the given address, rejecting anything the registry doesn't
+    /// know or the dialect doesn't allow.
+    pub fn decode(&self, memory: &[IntCode], pc: usize) -> Res<Instruction> {
+        if pc >= memory.len() {
+            return error(format!("Program counter out of range: {}", pc));
+        }
+        let value = memory[pc];
+        let spec = match self.spec(value % 100) {
+            // Day 2 instructions carry no parameter modes.
+            Some(spec) if self.dialect != Dialect::Day02 || value < 100 => spec,
+            _ => return error(format!("Invalid opcode: {}", value)),
+        };
+        let arity = spec.roles.len();
+        if pc + arity >= memory.len() {
+            return error(format!("Truncated instruction at {}", pc));
+        }
+
+        let mut params = Vec::with_capacity(arity);
+        let mut modes = value / 100;
+        for (index, &role) in spec.roles.iter().enumerate() {
+            let mut mode = ParameterMode::from_int(modes % 10)?;
+            if !self.dialect.supports_mode(mode) {
+                return error("Unsupported parameter mode.");
+            }
+            if role == Role::Write && mode == ParameterMode::Immediate {
+                // Destinations are always addresses, regardless of the mode digit.
+                mode = ParameterMode::Position;
+            }
+            params.push(Parameter {
+                mode,
+                role,
+                value: memory[pc + 1 + index],
+            });
+            modes /= 10;
+        }
+        Ok(Instruction {
+            opcode: spec.opcode,
+            mnemonic: spec.mnemonic,
+            params,
+        })
+    }
+}
+
+/// What a handler sees of the machine while executing an instruction.
+pub struct Context<'a> {
+    machine: &'a mut Machine,
+    step: Step,
+    next_pc: usize,
+}
+
+impl<'a> Context<'a> {
+    pub(crate) fn new(machine: &'a mut Machine, pc: usize, instruction: Instruction) -> Self {
+        let next_pc = pc + instruction.size();
+        Context {
+            machine,
+            step: Step {
+                pc,
+                instruction,
+                write: None,
+                branch: None,
+                input: None,
+                output: None,
+            },
+            next_pc,
+        }
+    }
+
+    pub(crate) fn finish(self) -> (Step, usize) {
+        (self.step, self.next_pc)
+    }
+
+    pub fn pc(&self) -> usize {
+        self.step.pc
+    }
+
+    pub fn instruction(&self) -> &Instruction {
+        &self.step.instruction
+    }
+
+    pub fn machine(&self) -> &Machine {
+        self.machine
+    }
+
+    /// The value of the given parameter.
+    pub fn arg(&self, index: usize) -> Res<IntCode> {
+        self.machine.read(self.param(index)?)
+    }
+
+    /// Write to the address named by the given parameter.
+    pub fn store(&mut self, index: usize, value: IntCode) -> Res<()> {
+        let param = self.param(index)?;
+        let addr = self.machine.resolve(param)?;
+        self.step.write = Some(self.machine.write(addr, value)?);
+        Ok(())
+    }
+
+    /// Read any cell of memory, as a position parameter would.
+    pub fn peek(&self, addr: IntCode) -> Res<IntCode> {
+        self.machine.read(Parameter {
+            mode: ParameterMode::Position,
+            role: Role::Read,
+            value: addr,
+        })
+    }
+
+    /// Write any cell of memory. Unlike `store`, the write isn't reported in the step.
+    pub fn write(&mut self, addr: IntCode, value: IntCode) -> Res<()> {
+        self.machine.write(addr, value)?;
+        Ok(())
+    }
+
+    /// The machine's host, for instructions that call out to it.
+    pub fn host(&self) -> Res<MutexGuard<'_, dyn HostCalls + 'static>> {
+        match self.machine.host.lock() {
+            Ok(host) => Ok(host),
+            Err(_) => error("Host is poisoned"),
+        }
+    }
+
+    /// Take the next queued input.
+    pub fn input(&mut self) -> Res<IntCode> {
+        match self.machine.inputs.pop_front() {
+            Some(value) => {
+                self.step.input = Some(value);
+                Ok(value)
+            }
+            None => error(format!("No input available at {}", self.step.pc)),
+        }
+    }
+
+    pub fn output(&mut self, value: IntCode) -> Res<()> {
+        self.machine.push_output(value)?;
+        self.step.output = Some(value);
+        Ok(())
+    }
+
+    /// Record a conditional jump, moving to `target` if it was taken.
+    pub fn branch(&mut self, taken: bool, target: IntCode) -> Res<()> {
+        if taken {
+            self.next_pc = jump_target(target, self.step.pc)?;
+        }
+        self.step.branch = Some(taken);
+        Ok(())
+    }
+
+    /// Record the outcome of a comparison, which coverage counts as a branch.
+    pub fn compare(&mut self, holds: bool) {
+        self.step.branch = Some(holds);
+    }
+
+    pub fn adjust_relative_base(&mut self, offset: IntCode) -> Res<()> {
+        self.machine.relative_base = match self.machine.relative_base.checked_add(offset) {
+            Some(base) => base,
+            None => return error(format!("Arithmetic overflow at {}", self.step.pc)),
+        };
+        Ok(())
+    }
+
+    /// Stop the machine, leaving the pc on this instruction.
+    pub fn halt(&mut self) {
+        self.machine.halted = true;
+        self.next_pc = self.step.pc;
+    }
+
+    fn param(&self, index: usize) -> Res<Parameter> {
+        match self.step.instruction.params.get(index) {
+            Some(&param) => Ok(param),
+            None => error(format!(
+                "{} has no parameter {}",
+                self.step.instruction.mnemonic, index
+            )),
+        }
+    }
+}
+
+fn arithmetic(context: &mut Context) -> Res<()> {
+    let opcode = context.instruction().opcode;
+    let value = alu(opcode, context.arg(0)?, context.arg(1)?, context.pc())?;
+    if opcode == Opcode::LessThan || opcode == Opcode::Equals {
+        context.compare(value != 0);
+    }
+    context.store(2, value)
+}
+
+fn input(context: &mut Context) -> Res<()> {
+    let value = context.input()?;
+    context.store(0, value)
+}
+
+fn output(context: &mut Context) -> Res<()> {
+    let value = context.arg(0)?;
+    context.output(value)
+}
+
+fn jump(context: &mut Context) -> Res<()> {
+    let condition = context.arg(0)?;
+    let target = context.arg(1)?;
+    let if_true = context.instruction().opcode == Opcode::JumpIfTrue;
+    context.branch((condition != 0) == if_true, target)
+}
+
+fn adjust_relative_base(context: &mut Context) -> Res<()> {
+    let offset = context.arg(0)?;
+    context.adjust_relative_base(offset)
+}
+
+fn halt(context: &mut Context) -> Res<()> {
+    context.halt();
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decode(memory: &[IntCode], pc: usize, dialect: Dialect) -> Res<Instruction> {
+        Registry::new(dialect).decode(memory, pc)
+    }
+
+    #[test]
+    fn decode_modes() -> Res<()> {
+        let instruction = decode(&[1002, 4, 3, 4, 33], 0, Dialect::Day05)?;
+        assert_eq!(instruction.opcode, Opcode::Multiply);
+        assert_eq!(instruction.size(), 4);
+        assert_eq!(instruction.params[0].mode, ParameterMode::Position);
+        assert_eq!(instruction.params[1].mode, ParameterMode::Immediate);
+        assert_eq!(instruction.destination().map(|param| param.value), Some(4));
+        assert_eq!(instruction.to_string(), "mul [4], 3, [4]");
+
+        let instruction = decode(&[21201, -3, 7, 2], 0, Dialect::Full)?;
+        assert_eq!(instruction.to_string(), "add [rb-3], 7, [rb+2]");
+        Ok(())
+    }
+
+    #[test]
+    fn decode_errors() {
+        assert!(decode(&[42], 0, Dialect::Full).is_err());
+        assert!(decode(&[1, 0, 0], 0, Dialect::Full).is_err());
+        assert!(decode(&[1, 0, 0, 0], 4, Dialect::Full).is_err());
+        assert!(decode(&[304, 0], 0, Dialect::Full).is_err());
+    }
+
+    #[test]
+    fn dialects() {
+        assert!(decode(&[1002, 4, 3, 4], 0, Dialect::Day02).is_err());
+        assert!(decode(&[3, 0], 0, Dialect::Day02).is_err());
+        assert!(decode(&[3, 0], 0, Dialect::Day05).is_ok());
+        assert!(decode(&[204, 0], 0, Dialect::Day05).is_err());
+        assert!(decode(&[204, 0], 0, Dialect::Full).is_ok());
+        assert!(decode(&[109, 0], 0, Dialect::Day05).is_err());
+        assert!(decode(&[109, 0], 0, Dialect::Full).is_ok());
+        assert_eq!("day02".parse::<Dialect>().unwrap(), Dialect::Day02);
+        assert!("day09".parse::<Dialect>().is_err());
+    }
+
+    fn double(context: &mut Context) -> Res<()> {
+        let value = context.arg(0)?;
+        context.store(1, value * 2)
+    }
+
+    #[test]
+    fn custom_opcodes() -> Res<()> {
+        let mut registry = Registry::new(Dialect::Day05);
+        registry.register(42, "dbl", &[Role::Read, Role::Write], double)?;
+        assert!(registry.register(42, "twice", &[], double).is_err());
+        assert!(registry.register(43, "add", &[], double).is_err());
+        assert!(registry.register(100, "big", &[], double).is_err());
+
+        let instruction = registry.decode(&[142, 21, 5], 0)?;
+        assert_eq!(instruction.opcode, Opcode::Custom(42));
+        assert_eq!(instruction.to_string(), "dbl 21, [5]");
+        assert_eq!(registry.by_mnemonic("dbl").unwrap().roles.len(), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,267 @@
+use crate::instruction::Opcode;
+use crate::machine::Machine;
+use crate::IntCode;
+use common::{error, Res};
+use std::fmt;
+
+/*
+ * Recording and replay of Intcode I/O sessions.
+ *
+ * Session files hold one event per line, `in <step> <value>` or `out <step> <value>`, where
+ * step is the number of instructions executed before the one doing the I/O. Blank lines and
+ * lines starting with `#` are ignored.
+ */
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Event {
+    Input { step: usize, value: IntCode },
+    Output { step: usize, value: IntCode },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Input { step, value } => write!(f, "in {} {}", step, value),
+            Event::Output { step, value } => write!(f, "out {} {}", step, value),
+        }
+    }
+}
+
+/// The first point at which a replay differed from its recording. `None` on either side
+/// means that side had no further events. An input request at the wrong step carries the
+/// value the recording would have supplied, or 0 if it had none.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Mismatch {
+    pub index: usize,
+    pub expected: Option<Event>,
+    pub actual: Option<Event>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let describe = |event: &Option<Event>| match event {
+            Some(event) => event.to_string(),
+            None => "end of session".to_string(),
+        };
+        write!(
+            f,
+            "event #{}: expected {}, got {}",
+            self.index,
+            describe(&self.expected),
+            describe(&self.actual)
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Session {
+    pub events: Vec<Event>,
+}
+
+impl Session {
+    /// Run the machine to completion, recording its I/O. Whenever the program wants input
+    /// and none is queued, `provide_input` is asked for the next value.
+    pub fn record<F: FnMut() -> Res<IntCode>>(
+        machine: &mut Machine,
+        mut provide_input: F,
+    ) -> Res<Session> {
+        let mut session = Session::default();
+        while !machine.is_halted() {
+            if machine.needs_input() {
+                machine.push_input(provide_input()?);
+            }
+            let step_count = machine.steps();
+            let step = machine.step()?;
+            if let Some(value) = step.input {
+                session.events.push(Event::Input {
+                    step: step_count,
+                    value,
+                });
+            }
+            if let Some(value) = step.output {
+                session.events.push(Event::Output {
+                    step: step_count,
+                    value,
+                });
+            }
+        }
+        Ok(session)
+    }
+
+    /// Replay the recorded inputs into the machine, stopping at the first divergence.
+    pub fn replay(&self, machine: &mut Machine) -> Res<Option<Mismatch>> {
+        for index in 0..=self.events.len() {
+            let expected = self.events.get(index).cloned();
+            let actual = next_event(machine, expected)?;
+            if actual != expected {
+                return Ok(Some(Mismatch {
+                    index,
+                    expected,
+                    actual,
+                }));
+            }
+            if actual.is_none() {
+                break;
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn parse(contents: &str) -> Res<Session> {
+        let mut events = vec![];
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (kind, step, value) = match fields.as_slice() {
+                [kind, step, value] => (*kind, step.parse::<usize>(), value.parse::<IntCode>()),
+                _ => return error(format!("Line {}: expected 3 fields: {}", index + 1, line)),
+            };
+            let (step, value) = match (step, value) {
+                (Ok(step), Ok(value)) => (step, value),
+                _ => return error(format!("Line {}: invalid number: {}", index + 1, line)),
+            };
+            events.push(match kind {
+                "in" => Event::Input { step, value },
+                "out" => Event::Output { step, value },
+                _ => return error(format!("Line {}: unknown event: {}", index + 1, kind)),
+            });
+        }
+        Ok(Session { events })
+    }
+}
+
+/// Run to the next I/O event. Input is only fed when `expected` says it is due at this step.
+fn next_event(machine: &mut Machine, expected: Option<Event>) -> Res<Option<Event>> {
+    while !machine.is_halted() {
+        let step = machine.steps();
+        if machine.registry.decode(&machine.memory, machine.pc)?.opcode == Opcode::Input {
+            return Ok(match expected {
+                Some(Event::Input {
+                    step: expected_step,
+                    value,
+                }) => {
+                    if expected_step == step {
+                        machine.push_input(value);
+                        machine.step()?;
+                    }
+                    Some(Event::Input { step, value })
+                }
+                _ => Some(Event::Input { step, value: 0 }),
+            });
+        }
+        if let Some(value) = machine.step()?.output {
+            return Ok(Some(Event::Output { step, value }));
+        }
+    }
+    Ok(None)
+}
+
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for event in &self.events {
+            writeln!(f, "{}", event)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Outputs 999, 1000 or 1001 for inputs below, equal to or above 8.
+    const COMPARE_TO_8: [IntCode; 47] = [
+        3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0, 0,
+        1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4, 20,
+        1105, 1, 46, 98, 99,
+    ];
+
+    fn record(inputs: Vec<IntCode>) -> Res<Session> {
+        let mut inputs = inputs.into_iter();
+        let mut machine = Machine::new(COMPARE_TO_8.to_vec());
+        Session::record(&mut machine, || match inputs.next() {
+            Some(value) => Ok(value),
+            None => error("Out of inputs"),
+        })
+    }
+
+    #[test]
+    fn record_and_parse() -> Res<()> {
+        let session = record(vec![8])?;
+        assert_eq!(
+            session.events,
+            vec![
+                Event::Input { step: 0, value: 8 },
+                Event::Output {
+                    step: 4,
+                    value: 1000
+                }
+            ]
+        );
+        assert_eq!(session.to_string(), "in 0 8\nout 4 1000\n");
+        assert_eq!(
+            Session::parse("# comment\n\nin 0 8\nout 4 1000\n")?,
+            session
+        );
+        assert!(Session::parse("in 0").is_err());
+        assert!(Session::parse("jump 0 1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn faithful_replay() -> Res<()> {
+        let session = record(vec![3])?;
+        let mut machine = Machine::new(COMPARE_TO_8.to_vec());
+        assert_eq!(session.replay(&mut machine)?, None);
+        assert_eq!(machine.outputs(), &[999]);
+        Ok(())
+    }
+
+    #[test]
+    fn divergent_output() -> Res<()> {
+        let session = Session::parse("in 0 8\nout 4 999\n")?;
+        let mut machine = Machine::new(COMPARE_TO_8.to_vec());
+        let mismatch = session.replay(&mut machine)?.unwrap();
+        assert_eq!(mismatch.index, 1);
+        assert_eq!(
+            mismatch.actual,
+            Some(Event::Output {
+                step: 4,
+                value: 1000
+            })
+        );
+        assert_eq!(
+            mismatch.to_string(),
+            "event #1: expected out 4 999, got out 4 1000"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn divergent_input_step() -> Res<()> {
+        let session = Session::parse("in 2 8\n")?;
+        let mut machine = Machine::new(COMPARE_TO_8.to_vec());
+        let mismatch = session.replay(&mut machine)?.unwrap();
+        assert_eq!(mismatch.index, 0);
+        assert_eq!(mismatch.actual, Some(Event::Input { step: 0, value: 8 }));
+
+        let session = Session::parse("out 0 1\n")?;
+        let mut machine = Machine::new(COMPARE_TO_8.to_vec());
+        let mismatch = session.replay(&mut machine)?.unwrap();
+        assert_eq!(mismatch.actual, Some(Event::Input { step: 0, value: 0 }));
+        Ok(())
+    }
+
+    #[test]
+    fn missing_events() -> Res<()> {
+        let session = Session::parse("in 0 8\nout 4 1000\nout 5 5\n")?;
+        let mut machine = Machine::new(COMPARE_TO_8.to_vec());
+        let mismatch = session.replay(&mut machine)?.unwrap();
+        assert_eq!(mismatch.index, 2);
+        assert_eq!(mismatch.actual, None);
+        Ok(())
+    }
+}